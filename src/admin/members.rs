@@ -1,10 +1,97 @@
 use crate::config::Context;
-use crate::handle_operation;
 use crate::util::{self, DrogueError, Outcome};
+use crate::{handle_operation, openid, ApplicationOperation};
+use anyhow::anyhow;
 
 use drogue_client::admin::v1::{Client, MemberEntry, Members, Role};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use strum_macros::AsRefStr;
 use tabular::{Row, Table};
 
+/// A members document, giving the role of each user by application.
+///
+/// ```yaml
+/// members:
+///   my-app:
+///     alice: admin
+///     bob: reader
+/// ```
+#[derive(Deserialize, Debug, Default)]
+pub struct MembersDocument {
+    pub members: BTreeMap<String, BTreeMap<String, Role>>,
+}
+
+/// The role changes applied to the members of an application.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct MembersDiff {
+    pub application: String,
+    pub added: BTreeMap<String, Role>,
+    /// The previous and new roles.
+    pub changed: BTreeMap<String, (Role, Role)>,
+    pub removed: BTreeMap<String, Role>,
+}
+
+impl MembersDiff {
+    /// Compare the current members with the expected roles. Users not listed are only
+    /// removed when pruning.
+    pub fn new(
+        application: &str,
+        current: &Members,
+        expected: &BTreeMap<String, Role>,
+        prune: bool,
+    ) -> Self {
+        let mut diff = MembersDiff {
+            application: application.to_string(),
+            added: BTreeMap::new(),
+            changed: BTreeMap::new(),
+            removed: BTreeMap::new(),
+        };
+
+        for (user, role) in expected {
+            match current.members.get(user) {
+                None => {
+                    diff.added.insert(user.clone(), *role);
+                }
+                Some(entry) if entry.role != *role => {
+                    diff.changed.insert(user.clone(), (entry.role, *role));
+                }
+                Some(_) => {}
+            }
+        }
+
+        if prune {
+            for (user, entry) in &current.members {
+                if !expected.contains_key(user) {
+                    diff.removed.insert(user.clone(), entry.role);
+                }
+            }
+        }
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    fn apply_to(&self, members: &mut Members) {
+        for (user, role) in &self.added {
+            members
+                .members
+                .insert(user.clone(), MemberEntry { role: *role });
+        }
+        for (user, (_, role)) in &self.changed {
+            members
+                .members
+                .insert(user.clone(), MemberEntry { role: *role });
+        }
+        for user in self.removed.keys() {
+            members.members.remove(user);
+        }
+    }
+}
+
 pub async fn member_list(config: &Context, app: &str) -> Result<Outcome<Members>, DrogueError> {
     let client = Client::new(
         reqwest::Client::new(),
@@ -84,6 +171,245 @@ pub async fn member_add(
     handle_operation!(op, "Application members updated")
 }
 
+/// Set the roles of the members of several applications, with one update per application.
+///
+/// An application failing to update does not stop the others, the failures are reported at the end.
+pub async fn members_apply(
+    config: &Context,
+    expected: &BTreeMap<String, BTreeMap<String, Role>>,
+    prune: bool,
+) -> Result<Outcome<Vec<MembersDiff>>, DrogueError> {
+    let client = Client::new(
+        reqwest::Client::new(),
+        config.registry_url.clone(),
+        config.token.clone(),
+    );
+
+    let mut diffs = Vec::new();
+    let mut failures = Vec::new();
+    for (app, roles) in expected {
+        let mut members = match client.get_members(app).await {
+            Ok(Some(members)) => members,
+            Ok(None) => {
+                failures.push(format!("{}: the application does not exist", app));
+                continue;
+            }
+            Err(e) => {
+                failures.push(format!("{}: cannot read the members: {}", app, e));
+                continue;
+            }
+        };
+
+        let diff = MembersDiff::new(app, &members, roles, prune);
+        if diff.is_empty() {
+            log::debug!("Members of application {} are up to date", app);
+            diffs.push(diff);
+            continue;
+        }
+
+        diff.apply_to(&mut members);
+        match client.update_members(app, members).await {
+            Ok(true) => diffs.push(diff),
+            Ok(false) => failures.push(format!("{}: the application does not exist", app)),
+            Err(e) => failures.push(format!("{}: cannot update the members: {}", app, e)),
+        }
+    }
+
+    if failures.is_empty() {
+        return Ok(Outcome::SuccessWithJsonData(diffs));
+    }
+
+    let mut message = format!(
+        "The members of {} application(s) were not applied:\n  {}",
+        failures.len(),
+        failures.join("\n  ")
+    );
+    let applied: Vec<&str> = diffs.iter().map(|d| d.application.as_str()).collect();
+    if !applied.is_empty() {
+        message = format!("{}\nApplied to: {}", message, applied.join(", "));
+    }
+    Err(DrogueError::UnexpectedClient(anyhow!(message)))
+}
+
+/// The role of a user on an application, as far as the caller can tell.
+#[derive(AsRefStr, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum AppRole {
+    Owner,
+    Admin,
+    Manager,
+    Reader,
+    /// A reader or a manager: the members list is only readable by administrators.
+    #[strum(serialize = "reader or manager")]
+    Member,
+}
+
+impl From<Role> for AppRole {
+    fn from(role: Role) -> Self {
+        match role {
+            Role::Admin => AppRole::Admin,
+            Role::Manager => AppRole::Manager,
+            Role::Reader => AppRole::Reader,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct AppPermission {
+    pub application: String,
+    pub user: String,
+    /// None when the user is not a member of the application.
+    pub role: Option<AppRole>,
+}
+
+/// The role of `user` on an application visible to `caller`. The owner is not part of the
+/// members, so the caller is the owner when it can read the members without being listed.
+/// For another user who is not listed, there is no way to tell whether they own the application.
+/// A drogue cloud administrator can read the members of every application, so is reported as
+/// the owner of all of them.
+fn effective_role(user: &str, caller: &str, members: Option<&Members>) -> Option<AppRole> {
+    match members {
+        Some(members) => match members.members.get(user) {
+            Some(entry) => Some(entry.role.into()),
+            None if user == caller => Some(AppRole::Owner),
+            None => None,
+        },
+        None if user == caller => Some(AppRole::Member),
+        None => None,
+    }
+}
+
+/// The role of a user on every application visible to the context. Without a user, the roles
+/// of the context user are given. For another user, only the applications whose members can be
+/// read are listed.
+pub async fn permissions(
+    config: &Context,
+    user: Option<&str>,
+) -> Result<Outcome<Vec<AppPermission>>, DrogueError> {
+    let caller = openid::username(config).ok_or_else(|| {
+        DrogueError::InvalidInput(format!(
+            "Cannot find the username of context {}",
+            config.name
+        ))
+    })?;
+    let user = user.unwrap_or(&caller);
+
+    let client = Client::new(
+        reqwest::Client::new(),
+        config.registry_url.clone(),
+        config.token.clone(),
+    );
+
+    let apps = match ApplicationOperation::new(None, None, None)?
+        .list(config, None)
+        .await?
+    {
+        Outcome::SuccessWithJsonData(apps) => apps,
+        Outcome::SuccessWithMessage(_) => Vec::new(),
+    };
+
+    let mut permissions = Vec::new();
+    for app in apps {
+        let name = app.metadata.name;
+        let members = match client.get_members(&name).await {
+            Ok(Some(members)) => Some(members),
+            Ok(None) => continue,
+            // Only the administrators of the application can read its members
+            Err(e) => match DrogueError::from(e) {
+                DrogueError::Service(msg, 403) => {
+                    log::debug!("Cannot read the members of application {}: {}", name, msg);
+                    None
+                }
+                e => return Err(e),
+            },
+        };
+
+        if members.is_none() && user != caller {
+            continue;
+        }
+        permissions.push(AppPermission {
+            role: effective_role(user, &caller, members.as_ref()),
+            application: name,
+            user: user.to_string(),
+        });
+    }
+
+    Ok(Outcome::SuccessWithJsonData(permissions))
+}
+
+pub fn permissions_table(permissions: &Vec<AppPermission>) {
+    if permissions.is_empty() {
+        println!("No application found.");
+        return;
+    }
+
+    let mut table = Table::new("{:<} | {:<} | {:<}");
+    table.add_row(
+        Row::new()
+            .with_cell("APPLICATION")
+            .with_cell("USER")
+            .with_cell("ROLE"),
+    );
+    for permission in permissions {
+        table.add_row(
+            Row::new()
+                .with_cell(&permission.application)
+                .with_cell(&permission.user)
+                .with_cell(permission.role.as_ref().map(|r| r.as_ref()).unwrap_or("-")),
+        );
+    }
+    print!("{}", table);
+}
+
+pub fn members_diff_table(diffs: &Vec<MembersDiff>) {
+    let mut table = Table::new("{:<} | {:<} | {:<}");
+    table.add_row(
+        Row::new()
+            .with_cell("APPLICATION")
+            .with_cell("USER")
+            .with_cell("CHANGE"),
+    );
+
+    let mut changes = 0;
+    for diff in diffs {
+        for (user, role) in &diff.added {
+            table.add_row(
+                Row::new()
+                    .with_cell(&diff.application)
+                    .with_cell(user)
+                    .with_cell(format!("+ {}", role)),
+            );
+        }
+        for (user, (previous, role)) in &diff.changed {
+            table.add_row(
+                Row::new()
+                    .with_cell(&diff.application)
+                    .with_cell(user)
+                    .with_cell(format!("{} -> {}", previous, role)),
+            );
+        }
+        for (user, role) in &diff.removed {
+            table.add_row(
+                Row::new()
+                    .with_cell(&diff.application)
+                    .with_cell(user)
+                    .with_cell(format!("- {}", role)),
+            );
+        }
+        changes += diff.added.len() + diff.changed.len() + diff.removed.len();
+    }
+
+    if changes > 0 {
+        print!("{}", table);
+    }
+    println!(
+        "{} member change(s) applied to {} application(s).",
+        changes,
+        diffs.iter().filter(|d| !d.is_empty()).count()
+    );
+}
+
 pub fn members_table(members: &Members) {
     let mut table = Table::new("{:<} | {:<}");
     table.add_row(Row::new().with_cell("USER").with_cell("ROLE"));
@@ -97,3 +423,73 @@ pub fn members_table(members: &Members) {
         println!("The member list for this application is empty.");
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn members(entries: &[(&str, Role)]) -> Members {
+        Members {
+            resource_version: Some("1".to_string()),
+            members: entries
+                .iter()
+                .map(|(user, role)| (user.to_string(), MemberEntry { role: *role }))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_effective_role() {
+        let current = members(&[("alice", Role::Admin), ("bob", Role::Reader)]);
+
+        assert_eq!(
+            effective_role("alice", "alice", Some(&current)),
+            Some(AppRole::Admin)
+        );
+        assert_eq!(
+            effective_role("bob", "alice", Some(&current)),
+            Some(AppRole::Reader)
+        );
+        assert_eq!(
+            effective_role("owner", "owner", Some(&current)),
+            Some(AppRole::Owner)
+        );
+        assert_eq!(effective_role("carol", "alice", Some(&current)), None);
+        assert_eq!(effective_role("bob", "bob", None), Some(AppRole::Member));
+    }
+
+    #[test]
+    fn test_members_diff() {
+        let mut current = members(&[
+            ("alice", Role::Reader),
+            ("bob", Role::Manager),
+            ("carol", Role::Admin),
+        ]);
+        let expected = BTreeMap::from([
+            ("alice".to_string(), Role::Admin),
+            ("bob".to_string(), Role::Manager),
+            ("dave".to_string(), Role::Reader),
+        ]);
+
+        let diff = MembersDiff::new("app", &current, &expected, false);
+        assert_eq!(
+            diff.added,
+            BTreeMap::from([("dave".to_string(), Role::Reader)])
+        );
+        assert_eq!(
+            diff.changed,
+            BTreeMap::from([("alice".to_string(), (Role::Reader, Role::Admin))])
+        );
+        assert!(diff.removed.is_empty());
+
+        let pruned = MembersDiff::new("app", &current, &expected, true);
+        assert_eq!(
+            pruned.removed,
+            BTreeMap::from([("carol".to_string(), Role::Admin)])
+        );
+
+        pruned.apply_to(&mut current);
+        assert!(MembersDiff::new("app", &current, &expected, true).is_empty());
+        assert_eq!(current.resource_version.as_deref(), Some("1"));
+    }
+}
@@ -1,12 +1,37 @@
-use crate::config::Context;
+use crate::config::{self, Context, Token};
 use crate::handle_operation;
 use crate::util;
 
+use chrono::{Duration, Utc};
+use regex::Regex;
+use std::io::{stdin, stdout, Write};
 use tabular::{Row, Table};
 
 use crate::util::{DrogueError, Outcome};
 use drogue_client::tokens::v1::{AccessToken, Client, CreatedAccessToken};
 
+/// Selects access tokens by age and description. An empty filter selects every token.
+pub struct TokenFilter {
+    pub older_than: Option<Duration>,
+    pub description: Option<Regex>,
+}
+
+impl TokenFilter {
+    pub fn matches(&self, token: &AccessToken) -> bool {
+        let old_enough = self
+            .older_than
+            .map(|age| token.created < Utc::now() - age)
+            .unwrap_or(true);
+        let described = self
+            .description
+            .as_ref()
+            .map(|re| re.is_match(token.description.as_deref().unwrap_or_default()))
+            .unwrap_or(true);
+
+        old_enough && described
+    }
+}
+
 pub async fn get_api_keys(config: &Context) -> Result<Outcome<Vec<AccessToken>>, DrogueError> {
     let client = Client::new(
         reqwest::Client::new(),
@@ -29,7 +54,37 @@ pub async fn create(
     handle_operation!(client.create_token(description).await)
 }
 
+pub async fn list(
+    config: &Context,
+    filter: &TokenFilter,
+) -> Result<Outcome<Vec<AccessToken>>, DrogueError> {
+    match get_api_keys(config).await? {
+        Outcome::SuccessWithJsonData(tokens) => Ok(Outcome::SuccessWithJsonData(
+            tokens.into_iter().filter(|t| filter.matches(t)).collect(),
+        )),
+        outcome => Ok(outcome),
+    }
+}
+
+// The prefix of the token the context is authenticated with is a prefix of the token itself
+fn is_current(config: &Context, prefix: &str) -> bool {
+    match &config.token {
+        Token::AccessToken(current) => !prefix.is_empty() && current.token.starts_with(prefix),
+        Token::TokenResponse(_)
+        | Token::ClientCredentials(_)
+        | Token::LoggedOut
+        | Token::Encrypted(_) => false,
+    }
+}
+
 pub async fn delete(config: &Context, prefix: &str) -> Result<Outcome<String>, DrogueError> {
+    if is_current(config, prefix) {
+        return Err(DrogueError::InvalidInput(format!(
+            "Context {} is authenticated with the access token {}, it cannot be deleted",
+            config.name, prefix
+        )));
+    }
+
     let client = Client::new(
         reqwest::Client::new(),
         config.drogue_cloud_url.clone(),
@@ -39,6 +94,157 @@ pub async fn delete(config: &Context, prefix: &str) -> Result<Outcome<String>, D
     handle_operation!(client.delete_token(prefix).await, "Access token deleted")
 }
 
+/// Delete the access tokens selected by the filter, except the one the context is authenticated
+/// with. Unless `confirmed`, the tokens are listed and a confirmation is asked first.
+///
+/// A token failing to be deleted does not stop the others, the failures are reported at the end.
+pub async fn prune(
+    config: &Context,
+    filter: &TokenFilter,
+    confirmed: bool,
+) -> Result<Outcome<String>, DrogueError> {
+    let tokens: Vec<AccessToken> = match list(config, filter).await? {
+        Outcome::SuccessWithJsonData(tokens) => tokens
+            .into_iter()
+            .filter(|t| {
+                let current = is_current(config, &t.prefix);
+                if current {
+                    log::warn!(
+                        "Skipping access token {}, context {} is authenticated with it",
+                        t.prefix,
+                        config.name
+                    );
+                }
+                !current
+            })
+            .collect(),
+        Outcome::SuccessWithMessage(_) => Vec::new(),
+    };
+
+    if tokens.is_empty() {
+        return Ok(Outcome::SuccessWithMessage(
+            "No access token to delete".to_string(),
+        ));
+    }
+
+    if !confirmed {
+        tokens_table(&tokens);
+        print!("Delete these {} access tokens? [y/N] ", tokens.len());
+        stdout().flush()?;
+        let mut answer = String::new();
+        stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            return Ok(Outcome::SuccessWithMessage(
+                "No access token deleted".to_string(),
+            ));
+        }
+    }
+
+    let client = Client::new(
+        reqwest::Client::new(),
+        config.drogue_cloud_url.clone(),
+        config.token.clone(),
+    );
+    let mut deleted = Vec::new();
+    let mut failures = Vec::new();
+    for token in &tokens {
+        match client.delete_token(&token.prefix).await {
+            Ok(true) => {
+                log::debug!("Access token {} deleted", token.prefix);
+                deleted.push(token.prefix.as_str());
+            }
+            Ok(false) => failures.push(format!("{}: not found", token.prefix)),
+            Err(e) => failures.push(format!("{}: {}", token.prefix, DrogueError::from(e))),
+        }
+    }
+
+    let mut message = format!("{} access tokens deleted", deleted.len());
+    if !deleted.is_empty() {
+        message = format!("{}: {}", message, deleted.join(", "));
+    }
+    if failures.is_empty() {
+        Ok(Outcome::SuccessWithMessage(message))
+    } else {
+        Err(DrogueError::UnexpectedClient(anyhow::anyhow!(
+            "{}\n{} access tokens could not be deleted:\n  {}",
+            message,
+            failures.len(),
+            failures.join("\n  ")
+        )))
+    }
+}
+
+/// The access token the context is authenticated with, as listed by drogue cloud.
+pub async fn current(context: &Context) -> Result<Option<AccessToken>, DrogueError> {
+    let tokens = match get_api_keys(context).await? {
+        Outcome::SuccessWithJsonData(tokens) => tokens,
+        Outcome::SuccessWithMessage(_) => Vec::new(),
+    };
+
+    Ok(tokens
+        .into_iter()
+        .filter(|t| is_current(context, &t.prefix))
+        .max_by_key(|t| t.prefix.len()))
+}
+
+/// Create a new access token for a context and switch the context to it, once the new token
+/// is validated. The old token is left untouched, its prefix is returned so it can be deleted
+/// after the new token is saved.
+pub async fn rotate(context: &mut Context) -> Result<Option<String>, DrogueError> {
+    let user = match &context.token {
+        Token::AccessToken(token) => token.id.clone(),
+        Token::TokenResponse(_)
+        | Token::ClientCredentials(_)
+        | Token::LoggedOut
+        | Token::Encrypted(_) => {
+            return Err(DrogueError::InvalidInput(format!(
+                "Context {} is not logged in with an access token",
+                context.name
+            )))
+        }
+    };
+
+    // The token list gives the prefix and description of the current token
+    let current = current(context).await?;
+
+    let created = match create(
+        context,
+        current.as_ref().and_then(|t| t.description.as_deref()),
+    )
+    .await?
+    {
+        Outcome::SuccessWithJsonData(created) => created,
+        Outcome::SuccessWithMessage(msg) => {
+            return Err(DrogueError::UnexpectedClient(anyhow::anyhow!(msg)))
+        }
+    };
+
+    let mut rotated = context.clone();
+    rotated.token = Token::AccessToken(config::AccessToken {
+        id: user,
+        token: created.token,
+    });
+
+    if let Err(e) = util::get_drogue_endpoints_authenticated(&rotated).await {
+        // Keep the old token, and do not leave an unused one behind
+        if let Err(e) = delete(context, &created.prefix).await {
+            log::warn!(
+                "Cannot delete the new access token {}: {}",
+                created.prefix,
+                e
+            );
+        }
+        return Err(DrogueError::InvalidInput(format!(
+            "The new access token was rejected, keeping the current one: {}",
+            e
+        )));
+    }
+
+    log::debug!("Access token {} validated", created.prefix);
+    context.token = rotated.token;
+    Ok(current.map(|t| t.prefix))
+}
+
 pub fn tokens_table(tokens: &Vec<AccessToken>) {
     let mut table = Table::new("{:<} | {:<} | {:<}");
     table.add_row(
@@ -64,3 +270,32 @@ pub fn created_token_print(token: &CreatedAccessToken) {
     println!("{}", token.token);
     println!("Make sure you save it, as you will not be able to display it again.");
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_token_filter() {
+        let token = |days: i64, description: Option<&str>| AccessToken {
+            created: Utc::now() - Duration::days(days),
+            prefix: "drg_abc".to_string(),
+            description: description.map(|d| d.to_string()),
+        };
+        let filter = TokenFilter {
+            older_than: Some(Duration::days(90)),
+            description: Some(Regex::new("^ci-").unwrap()),
+        };
+
+        assert!(filter.matches(&token(100, Some("ci-runner"))));
+        assert!(!filter.matches(&token(10, Some("ci-runner"))));
+        assert!(!filter.matches(&token(100, Some("laptop"))));
+        assert!(!filter.matches(&token(100, None)));
+
+        let all = TokenFilter {
+            older_than: None,
+            description: None,
+        };
+        assert!(all.matches(&token(0, None)));
+    }
+}
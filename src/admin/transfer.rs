@@ -1,9 +1,16 @@
 use crate::config::Context;
-use crate::handle_operation;
 use crate::util::{self, DrogueError, Outcome};
+use crate::{handle_operation, openid, ApplicationOperation};
 
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
 use drogue_client::admin::v1::Client;
 use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use strum_macros::AsRefStr;
+use tabular::{Row, Table};
 use url::Url;
 
 pub async fn transfer_app(
@@ -19,6 +26,9 @@ pub async fn transfer_app(
 
     match client.initiate_app_transfer(app, user).await {
         Ok(true) => {
+            if let Err(e) = TransferLog::for_context(config).and_then(|log| log.initiated(app)) {
+                log::warn!("Cannot record the transfer initiation time: {}", e);
+            }
             let console = util::get_drogue_console_endpoint(config).await.ok();
             Ok(Outcome::SuccessWithJsonData(AppTransfer {
                 console,
@@ -37,10 +47,12 @@ pub async fn cancel_transfer(config: &Context, app: &str) -> Result<Outcome<Stri
         config.token.clone(),
     );
 
-    handle_operation!(
-        client.cancel_app_transfer(app).await,
-        "Application transfer canceled"
-    )
+    let op = client.cancel_app_transfer(app).await;
+    if let Ok(true) = op {
+        forget_transfer(config, app);
+    }
+
+    handle_operation!(op, "Application transfer canceled")
 }
 
 pub async fn accept_transfer(config: &Context, app: &str) -> Result<Outcome<String>, DrogueError> {
@@ -50,12 +62,235 @@ pub async fn accept_transfer(config: &Context, app: &str) -> Result<Outcome<Stri
         config.token.clone(),
     );
 
+    let op = client.accept_app_transfer(app).await;
+    if let Ok(true) = op {
+        forget_transfer(config, app);
+    }
+
     handle_operation!(
-        client.accept_app_transfer(app).await,
+        op,
         "Application transfer completed. \n You are now the owner of the application"
     )
 }
 
+/// The application id of a transfer, given as is or as the console link printed by `transfer init`.
+pub fn transfer_app_id(transfer: &str) -> Result<String, DrogueError> {
+    let link = match Url::parse(transfer) {
+        Ok(link) if link.has_host() => link,
+        _ => return Ok(transfer.to_string()),
+    };
+
+    let segments: Vec<&str> = link
+        .path_segments()
+        .map(|s| s.collect())
+        .unwrap_or_default();
+    match segments.as_slice() {
+        [.., "transfer", app] if !app.is_empty() => urlencoding::decode(app)
+            .map_err(|_| DrogueError::InvalidInput(format!("Invalid transfer link {}", transfer))),
+        _ => Err(DrogueError::InvalidInput(format!(
+            "{} is not an application transfer link",
+            transfer
+        ))),
+    }
+}
+
+#[derive(AsRefStr, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum TransferDirection {
+    /// Initiated by the current user.
+    Outgoing,
+    /// Offered to the current user.
+    Incoming,
+    /// The username cannot be read from the context token, e.g. with an access token.
+    Unknown,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingTransfer {
+    pub app: String,
+    pub new_user: String,
+    /// Only known when the transfer was initiated with drg from this machine.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initiated: Option<DateTime<Utc>>,
+    pub direction: TransferDirection,
+}
+
+pub async fn transfer_status(
+    config: &Context,
+    app: &str,
+) -> Result<Outcome<Vec<PendingTransfer>>, DrogueError> {
+    let client = Client::new(
+        reqwest::Client::new(),
+        config.registry_url.clone(),
+        config.token.clone(),
+    );
+
+    let log = TransferLog::for_context(config)?;
+    match pending_transfer(&client, config, &log, app).await? {
+        Some(transfer) => Ok(Outcome::SuccessWithJsonData(vec![transfer])),
+        None => Ok(Outcome::SuccessWithMessage(format!(
+            "No transfer pending for application {}",
+            app
+        ))),
+    }
+}
+
+/// The pending transfers of the applications visible to the current user.
+///
+/// The transfers offered to the user are only found for the applications they can already list,
+/// as drogue cloud has no way to list them otherwise: `transfer_status` reads a given application.
+pub async fn list_transfers(
+    config: &Context,
+) -> Result<Outcome<Vec<PendingTransfer>>, DrogueError> {
+    let client = Client::new(
+        reqwest::Client::new(),
+        config.registry_url.clone(),
+        config.token.clone(),
+    );
+
+    let apps = match ApplicationOperation::new(None, None, None)?
+        .list(config, None)
+        .await?
+    {
+        Outcome::SuccessWithJsonData(apps) => apps,
+        Outcome::SuccessWithMessage(_) => Vec::new(),
+    };
+
+    let log = TransferLog::for_context(config)?;
+    let mut transfers = Vec::new();
+    for app in apps {
+        if let Some(transfer) = pending_transfer(&client, config, &log, &app.metadata.name).await? {
+            transfers.push(transfer);
+        }
+    }
+
+    Ok(Outcome::SuccessWithJsonData(transfers))
+}
+
+async fn pending_transfer(
+    client: &Client,
+    config: &Context,
+    log: &TransferLog,
+    app: &str,
+) -> Result<Option<PendingTransfer>, DrogueError> {
+    let transfer = match client.read_app_transfer(app).await {
+        Ok(Some(transfer)) => transfer,
+        Ok(None) => return Ok(None),
+        Err(e) => match DrogueError::from(e) {
+            // Only the owner and the new user can see the transfer
+            DrogueError::Service(msg, 403 | 404) => {
+                log::debug!("Cannot read the transfer of application {}: {}", app, msg);
+                return Ok(None);
+            }
+            e => return Err(e),
+        },
+    };
+
+    let direction = match openid::username(config) {
+        Some(username) if username == transfer.new_user => TransferDirection::Incoming,
+        Some(_) => TransferDirection::Outgoing,
+        None => TransferDirection::Unknown,
+    };
+
+    Ok(Some(PendingTransfer {
+        app: app.to_string(),
+        new_user: transfer.new_user,
+        initiated: log.read().get(app).copied(),
+        direction,
+    }))
+}
+
+fn forget_transfer(config: &Context, app: &str) {
+    if let Err(e) = TransferLog::for_context(config).and_then(|log| log.forget(app)) {
+        log::debug!("Cannot update the transfers log: {}", e);
+    }
+}
+
+/// When the transfers were initiated, as drogue cloud does not keep it.
+struct TransferLog {
+    path: PathBuf,
+}
+
+impl TransferLog {
+    fn for_context(config: &Context) -> Result<Self, DrogueError> {
+        let dir = dirs::data_dir()
+            .ok_or_else(|| DrogueError::ConfigIssue("Cannot find the user data directory".into()))?
+            .join("drg")
+            .join("transfers");
+
+        // Keyed on the drogue cloud instance, so that renaming the context keeps the log
+        Ok(TransferLog {
+            path: dir.join(format!("{}.yaml", config.instance_id())),
+        })
+    }
+
+    fn read(&self) -> BTreeMap<String, DateTime<Utc>> {
+        fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|content| serde_yaml::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn write(&self, entries: &BTreeMap<String, DateTime<Utc>>) -> Result<(), DrogueError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(
+            &self.path,
+            serde_yaml::to_string(entries).map_err(|e| anyhow!(e))?,
+        )?;
+        Ok(())
+    }
+
+    fn initiated(&self, app: &str) -> Result<(), DrogueError> {
+        let mut entries = self.read();
+        entries.insert(app.to_string(), Utc::now());
+        self.write(&entries)
+    }
+
+    fn forget(&self, app: &str) -> Result<(), DrogueError> {
+        let mut entries = self.read();
+        if entries.remove(app).is_some() {
+            self.write(&entries)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn transfers_table(transfers: &Vec<PendingTransfer>) {
+    if transfers.is_empty() {
+        println!("No pending application transfers.");
+        return;
+    }
+
+    let mut table = Table::new("{:<} | {:<} | {:<} | {:<}");
+    table.add_row(
+        Row::new()
+            .with_cell("APPLICATION")
+            .with_cell("DIRECTION")
+            .with_cell("NEW OWNER")
+            .with_cell("INITIATED"),
+    );
+
+    for transfer in transfers {
+        table.add_row(
+            Row::new()
+                .with_cell(&transfer.app)
+                .with_cell(transfer.direction.as_ref())
+                .with_cell(&transfer.new_user)
+                .with_cell(
+                    transfer
+                        .initiated
+                        .map(|t| t.to_rfc3339())
+                        .unwrap_or_else(|| "unknown".to_string()),
+                ),
+        );
+    }
+    print!("{}", table);
+}
+
 #[derive(Serialize)]
 pub struct AppTransfer {
     console: Option<Url>,
@@ -65,7 +300,7 @@ pub struct AppTransfer {
 pub fn app_transfer_guide(transfer: &AppTransfer) {
     println!("Application transfer initated.");
     println!(
-        "The new user can accept the transfer with \"drg admin transfer accept {}\"",
+        "The new user can accept the transfer with \"drg transfer accept {}\"",
         transfer.app
     );
 
@@ -77,3 +312,18 @@ pub fn app_transfer_guide(transfer: &AppTransfer) {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transfer_app_id() {
+        assert_eq!(transfer_app_id("my-app").unwrap(), "my-app");
+        assert_eq!(
+            transfer_app_id("https://console.example.com/transfer/my%20app").unwrap(),
+            "my app"
+        );
+        assert!(transfer_app_id("https://console.example.com/apps/my-app").is_err());
+    }
+}
@@ -9,7 +9,9 @@ use serde_json::{json, Value};
 use tabular::{Row, Table};
 
 use drogue_client::registry::v1::Client;
-use drogue_client::registry::v1::{Application, ApplicationSpecTrustAnchors};
+use drogue_client::registry::v1::{
+    Application, ApplicationSpecTrustAnchorEntry, ApplicationSpecTrustAnchors,
+};
 use drogue_client::Translator;
 
 impl ApplicationOperation {
@@ -105,19 +107,8 @@ impl ApplicationOperation {
     pub async fn add_trust_anchor(
         &self,
         config: &Context,
-        keyout: Option<&str>,
-        key_pair_algorithm: Option<util::SignAlgo>,
-        days: Option<&str>,
-        key_input: Option<rcgen::KeyPair>,
+        trust_anchor: ApplicationSpecTrustAnchorEntry,
     ) -> Result<Outcome<String>, DrogueError> {
-        let trust_anchor = util::create_trust_anchor(
-            self.name.as_ref().unwrap(),
-            keyout,
-            key_pair_algorithm,
-            days,
-            key_input,
-        )?;
-
         let anchors = ApplicationSpecTrustAnchors {
             anchors: vec![trust_anchor],
         };
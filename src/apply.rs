@@ -1,7 +1,11 @@
+use crate::admin::{self, MembersDiff, MembersDocument};
 use crate::DrogueError::InvalidInput;
-use crate::{ApplicationOperation, Context, DeviceOperation, DrogueError, Outcome};
+use crate::{display, ApplicationOperation, Context, DeviceOperation, DrogueError, Outcome};
+use anyhow::anyhow;
+use drogue_client::admin::v1::Role;
 use drogue_client::registry::v1::{Application, Device};
 use serde_json::Value;
+use std::collections::BTreeMap;
 use std::fs::{read_dir, File};
 use std::io;
 use std::io::BufReader;
@@ -10,6 +14,7 @@ use std::path::PathBuf;
 enum Resource {
     Device(Device),
     Application(Application),
+    Members(MembersDocument),
 }
 
 enum ResourceName {
@@ -28,30 +33,10 @@ pub async fn apply(
     config: &Context,
     paths: Vec<&PathBuf>,
     ignore_resource_version: bool,
+    json: bool,
 ) -> Result<Outcome<String>, DrogueError> {
-    let mut resources: Vec<Resource> = Vec::new();
-
-    // explore directories and load every file there
-    for p in paths {
-        if p.is_dir() {
-            for file in read_dir(p)? {
-                match load_json(&file.unwrap().path()) {
-                    Ok(r) => resources.push(r),
-                    Err(e) => log::error!("{e}"),
-                }
-            }
-        } else if p == &PathBuf::from("-") {
-            match std_in() {
-                Ok(r) => resources.push(r),
-                Err(e) => log::error!("{e}"),
-            }
-        } else {
-            match load_json(p) {
-                Ok(r) => resources.push(r),
-                Err(e) => log::error!("Cannot read file {:?} -> {e}", p),
-            }
-        }
-    }
+    let resources = load_resources(paths)?;
+    let mut failed_members = false;
 
     for mut r in resources {
         match r {
@@ -124,13 +109,87 @@ pub async fn apply(
                     ExistenceOutcome::NoApp => unreachable!(),
                 }
             }
+            Resource::Members(ref doc) => {
+                let code = display(
+                    admin::members_apply(config, &doc.members, false).await,
+                    json,
+                    admin::members_diff_table,
+                )?;
+                failed_members |= code != 0;
+            }
         }
     }
 
+    if failed_members {
+        return Err(DrogueError::UnexpectedClient(anyhow!(
+            "Some members documents were not fully applied"
+        )));
+    }
+
     //fixme drg don't really handles multiple operations yet :)
     Ok(Outcome::SuccessWithMessage("Finished apply".to_string()))
 }
 
+/// Apply members documents, merged together. With `prune`, the users not listed are removed
+/// from the applications of the documents.
+pub async fn apply_members(
+    config: &Context,
+    paths: Vec<&PathBuf>,
+    prune: bool,
+) -> Result<Outcome<Vec<MembersDiff>>, DrogueError> {
+    let mut expected: BTreeMap<String, BTreeMap<String, Role>> = BTreeMap::new();
+
+    for resource in load_resources(paths)? {
+        match resource {
+            Resource::Members(doc) => {
+                for (app, roles) in doc.members {
+                    expected.entry(app).or_default().extend(roles);
+                }
+            }
+            Resource::Device(dev) => {
+                log::warn!(
+                    "Skipping device {}, not a members document",
+                    dev.metadata.name
+                )
+            }
+            Resource::Application(app) => log::warn!(
+                "Skipping application {}, not a members document",
+                app.metadata.name
+            ),
+        }
+    }
+
+    admin::members_apply(config, &expected, prune).await
+}
+
+// explore directories and load every file there
+fn load_resources(paths: Vec<&PathBuf>) -> Result<Vec<Resource>, DrogueError> {
+    let mut resources: Vec<Resource> = Vec::new();
+
+    for p in paths {
+        if p.is_dir() {
+            for file in read_dir(p)? {
+                match load_json(&file.unwrap().path()) {
+                    Ok(r) => resources.push(r),
+                    Err(e) => log::error!("{e}"),
+                }
+            }
+        } else if p == &PathBuf::from("-") {
+            match std_in() {
+                Ok(r) => resources.push(r),
+                Err(e) => log::error!("{e}"),
+            }
+        } else {
+            match load_json(p) {
+                Ok(r) => resources.push(r),
+                Err(e) => log::error!("Cannot read file {:?} -> {e}", p),
+            }
+        }
+    }
+
+    Ok(resources)
+}
+
 fn std_in() -> Result<Resource, DrogueError> {
     let stdin = io::stdin();
     let reader = BufReader::new(stdin);
@@ -157,7 +216,20 @@ fn load_json(path: &PathBuf) -> Result<Resource, DrogueError> {
 }
 
 fn deser(json: Value) -> Result<Resource, DrogueError> {
-    if json.get("metadata").unwrap().get("application").is_some() {
+    let metadata = match json.get("metadata") {
+        Some(metadata) => metadata,
+        None if json.get("members").is_some() => {
+            let doc: MembersDocument = serde_json::from_value(json)?;
+            return Ok(Resource::Members(doc));
+        }
+        None => {
+            return Err(InvalidInput(
+                "Not a device, an application or a members document".to_string(),
+            ))
+        }
+    };
+
+    if metadata.get("application").is_some() {
         let dev: Device = serde_json::from_value(json)?;
         Ok(Resource::Device(dev))
     } else {
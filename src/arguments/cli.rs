@@ -1,6 +1,7 @@
 use crate::util;
 use clap::{value_parser, Arg, ArgGroup, Command};
 use std::convert::AsRef;
+use std::net::IpAddr;
 use std::path::PathBuf;
 use strum_macros::{AsRefStr, EnumString};
 
@@ -24,6 +25,10 @@ pub enum Action {
     version,
     whoami,
     config,
+    audit,
+    renew,
+    rotate,
+    logout,
 }
 
 #[derive(AsRefStr, EnumString)]
@@ -33,6 +38,7 @@ pub enum Transfer {
     init,
     accept,
     cancel,
+    status,
 }
 
 #[derive(AsRefStr, EnumString, PartialEq, Eq, Debug)]
@@ -47,6 +53,8 @@ pub enum ResourceType {
     device_cert,
     token,
     context,
+    certs,
+    transfer,
 
     // resources for the set command
     gateway,
@@ -98,6 +106,7 @@ pub enum Parameters {
     labels,
     #[strum(serialize = "ignore-missing")]
     ignore_missing,
+    prune,
 
     // specific to certificate commands (create app-cert & create device-cert)
     #[strum(serialize = "key-input")]
@@ -109,6 +118,51 @@ pub enum Parameters {
     cert_output,
     days,
     algo,
+    csr,
+    #[strum(serialize = "subject-org")]
+    subject_org,
+    #[strum(serialize = "subject-country")]
+    subject_country,
+    #[strum(serialize = "subject-state")]
+    subject_state,
+    #[strum(serialize = "subject-locality")]
+    subject_locality,
+    #[strum(serialize = "san-dns")]
+    san_dns,
+    #[strum(serialize = "san-uri")]
+    san_uri,
+    #[strum(serialize = "san-ip")]
+    san_ip,
+    #[strum(serialize = "key-usage")]
+    key_usage,
+    #[strum(serialize = "ext-key-usage")]
+    ext_key_usage,
+    #[strum(serialize = "path-len")]
+    path_len,
+    format,
+    #[strum(serialize = "key-passphrase")]
+    key_passphrase,
+    #[strum(serialize = "key-passphrase-stdin")]
+    key_passphrase_stdin,
+    #[strum(serialize = "issuer-cert")]
+    issuer_cert,
+    #[strum(serialize = "issuer-key")]
+    issuer_key,
+    #[strum(serialize = "csr-output")]
+    csr_output,
+    #[strum(serialize = "import-signed")]
+    import_signed,
+    #[strum(serialize = "rsa-bits")]
+    rsa_bits,
+    #[strum(serialize = "allow-algo")]
+    allow_algo,
+    #[strum(serialize = "min-rsa-bits")]
+    min_rsa_bits,
+
+    // audit command
+    within,
+    #[strum(serialize = "issued-certs")]
+    issued_certs,
 
     // send command
     command,
@@ -133,9 +187,44 @@ pub enum Parameters {
     // login & whoami command
     token,
     endpoints,
+    claims,
+    permissions,
+    user,
     description,
     #[strum(serialize = "access-token")]
     access_token,
+    #[strum(serialize = "create-access-token")]
+    create_access_token,
+    #[strum(serialize = "device-code")]
+    device_code,
+    #[strum(serialize = "client-id")]
+    client_id,
+    #[strum(serialize = "client-secret-file")]
+    client_secret_file,
+    scope,
+    #[strum(serialize = "callback-host")]
+    callback_host,
+    #[strum(serialize = "callback-port")]
+    callback_port,
+
+    // logout command
+    all,
+    purge,
+
+    // config command
+    #[strum(serialize = "show-secrets")]
+    show_secrets,
+    cluster,
+    #[strum(serialize = "with-secrets")]
+    with_secrets,
+    base64,
+
+    // token filters
+    #[strum(serialize = "older-than")]
+    older_than,
+    #[strum(serialize = "description-match")]
+    description_match,
+    yes,
 
     interactive,
 }
@@ -194,7 +283,7 @@ pub fn app_arguments() -> clap::Command<'static> {
         .value_name("path/to/key")
         .takes_value(true)
         .required(true)
-        .help("Private key of the CA i.e application.");
+        .help("Private key of the CA i.e application. Accepts the same formats as --key-input.");
 
     let keyout = Arg::new(Parameters::key_output.as_ref())
         .takes_value(true)
@@ -207,7 +296,13 @@ pub fn app_arguments() -> clap::Command<'static> {
         .value_name("path/to/key")
         .takes_value(true)
         .required(false)
-        .help("Input private key to be used to sign CA/device certificates.");
+        .help("Input private key to be used to sign CA/device certificates.")
+        .long_help(
+            "Input private key to be used to sign CA/device certificates. \
+            PEM or DER keys are accepted, in PKCS#8, encrypted PKCS#8, PKCS#1 (RSA) or SEC1 (EC) format. \
+            The passphrase of an encrypted key is read from the DRG_KEY_PASSPHRASE environment variable, \
+            or prompted for.",
+        );
 
     let device_name_subj = Arg::new(Parameters::cert.as_ref())
         .long(Parameters::cert.as_ref())
@@ -231,8 +326,8 @@ pub fn app_arguments() -> clap::Command<'static> {
         .takes_value(true)
         .required(false)
         .help("Number of days the certificate should be valid for. [default: 365]")
-        .validator(|n| match n.parse::<u64>() {
-            Err(_) => Err(String::from("The value is not an integer")),
+        .validator(|n| match n.parse::<u32>() {
+            Err(_) => Err(String::from("The value is not a positive integer")),
             Ok(_) => Ok(()),
         });
 
@@ -241,8 +336,10 @@ pub fn app_arguments() -> clap::Command<'static> {
         .help("Algorithm used to generate key pair.")
         .possible_values([
             util::SignAlgo::ECDSA.as_ref(),
+            util::SignAlgo::ECDSA384.as_ref(),
             util::SignAlgo::EdDSA.as_ref(),
             util::SignAlgo::RSA.as_ref(),
+            util::SignAlgo::RSAPSS.as_ref(),
         ]);
 
     let key_pair_algorithm = algo_param
@@ -251,6 +348,192 @@ pub fn app_arguments() -> clap::Command<'static> {
         .takes_value(true)
         .long(Parameters::algo.as_ref());
 
+    let rsa_bits = Arg::new(Parameters::rsa_bits.as_ref())
+        .long(Parameters::rsa_bits.as_ref())
+        .takes_value(true)
+        .value_name("bits")
+        .possible_values(["2048", "3072", "4096"])
+        .help("Size of the generated RSA keys, implies the RSA algorithm. [default: 2048]");
+
+    let csr = Arg::new(Parameters::csr.as_ref())
+        .long(Parameters::csr.as_ref())
+        .value_name("path/to/csr")
+        .takes_value(true)
+        .conflicts_with_all(&[
+            Parameters::key_input.as_ref(),
+            Parameters::key_output.as_ref(),
+            Parameters::algo.as_ref(),
+        ])
+        .help("Sign a certificate signing request (PEM or DER) generated by the device instead of generating a key pair.")
+        .long_help(
+            "Sign a PKCS#10 certificate signing request (PEM or DER) generated by the device \
+            instead of generating a key pair. The CSR signature is verified and its subject must match \
+            the one drg would generate, \"CN=<deviceId>, O=Drogue IoT, OU=<appId>\" by default.",
+        );
+
+    let issuer_args = [
+        Arg::new(Parameters::issuer_cert.as_ref())
+            .long(Parameters::issuer_cert.as_ref())
+            .value_name("path/to/cert")
+            .takes_value(true)
+            // The application certificate is either signed here or offline
+            .requires("issuer")
+            .help("Certificate (PEM) of the CA issuing the application certificate, optionally followed by its chain.")
+            .long_help(
+                "Certificate (PEM) of the CA issuing the application certificate, optionally followed by \
+                the rest of its chain. With --issuer-key, the application certificate is an intermediate \
+                signed by this CA. With --import-signed, it completes the chain of the signed certificate.",
+            ),
+        Arg::new(Parameters::issuer_key.as_ref())
+            .long(Parameters::issuer_key.as_ref())
+            .value_name("path/to/key")
+            .takes_value(true)
+            .requires(Parameters::issuer_cert.as_ref())
+            .conflicts_with_all(&[
+                Parameters::csr_output.as_ref(),
+                Parameters::import_signed.as_ref(),
+            ])
+            .help("Private key of the issuing CA. Accepts the same formats as --key-input."),
+        Arg::new(Parameters::csr_output.as_ref())
+            .long(Parameters::csr_output.as_ref())
+            .value_name("path/to/csr")
+            .takes_value(true)
+            .conflicts_with_all(&[
+                Parameters::import_signed.as_ref(),
+                Parameters::days.as_ref(),
+            ])
+            .help("Write a certificate signing request to be signed offline, instead of uploading a trust anchor."),
+        Arg::new(Parameters::import_signed.as_ref())
+            .long(Parameters::import_signed.as_ref())
+            .value_name("path/to/cert")
+            .takes_value(true)
+            .requires(Parameters::key_input.as_ref())
+            .conflicts_with_all(&[
+                Parameters::key_output.as_ref(),
+                Parameters::algo.as_ref(),
+                Parameters::days.as_ref(),
+            ])
+            .help("Upload the application certificate (PEM) signed from the --csr-output request, with its chain.")
+            .long_help(
+                "Upload the application certificate (PEM) signed from the --csr-output request, followed by \
+                its chain or completed with --issuer-cert. --key-input must be the key the request was \
+                generated with.",
+            ),
+    ];
+
+    let cert_profile_args = [
+        Arg::new(Parameters::subject_org.as_ref())
+            .long(Parameters::subject_org.as_ref())
+            .takes_value(true)
+            .value_name("organization")
+            .help("Organization (O) of the certificate subject. [default: Drogue IoT]"),
+        Arg::new(Parameters::subject_country.as_ref())
+            .long(Parameters::subject_country.as_ref())
+            .takes_value(true)
+            .value_name("code")
+            .help("Country (C) of the certificate subject."),
+        Arg::new(Parameters::subject_state.as_ref())
+            .long(Parameters::subject_state.as_ref())
+            .takes_value(true)
+            .value_name("state")
+            .help("State or province (ST) of the certificate subject."),
+        Arg::new(Parameters::subject_locality.as_ref())
+            .long(Parameters::subject_locality.as_ref())
+            .takes_value(true)
+            .value_name("locality")
+            .help("Locality (L) of the certificate subject."),
+        Arg::new(Parameters::san_dns.as_ref())
+            .long(Parameters::san_dns.as_ref())
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .value_name("name")
+            .help("DNS name to add to the subject alternative names. Can be repeated."),
+        Arg::new(Parameters::san_uri.as_ref())
+            .long(Parameters::san_uri.as_ref())
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .value_name("uri")
+            .help("URI to add to the subject alternative names. Can be repeated.")
+            .validator(|u| url::Url::parse(u).map(|_| ())),
+        Arg::new(Parameters::san_ip.as_ref())
+            .long(Parameters::san_ip.as_ref())
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .value_name("address")
+            .help("IP address to add to the subject alternative names. Can be repeated.")
+            .validator(|ip| ip.parse::<IpAddr>().map(|_| ())),
+        Arg::new(Parameters::key_usage.as_ref())
+            .long(Parameters::key_usage.as_ref())
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .use_value_delimiter(true)
+            .value_name("usage")
+            .help("Key usages of the certificate. Can be repeated or comma separated.")
+            .possible_values([
+                util::KeyUsage::DigitalSignature.as_ref(),
+                util::KeyUsage::ContentCommitment.as_ref(),
+                util::KeyUsage::KeyEncipherment.as_ref(),
+                util::KeyUsage::DataEncipherment.as_ref(),
+                util::KeyUsage::KeyAgreement.as_ref(),
+                util::KeyUsage::KeyCertSign.as_ref(),
+                util::KeyUsage::CrlSign.as_ref(),
+            ]),
+        Arg::new(Parameters::ext_key_usage.as_ref())
+            .long(Parameters::ext_key_usage.as_ref())
+            .takes_value(true)
+            .multiple_occurrences(true)
+            .use_value_delimiter(true)
+            .value_name("usage")
+            .help("Extended key usages of the certificate. Can be repeated or comma separated. [default for devices: serverAuth,clientAuth]")
+            .possible_values([
+                util::ExtendedKeyUsage::ServerAuth.as_ref(),
+                util::ExtendedKeyUsage::ClientAuth.as_ref(),
+                util::ExtendedKeyUsage::CodeSigning.as_ref(),
+                util::ExtendedKeyUsage::EmailProtection.as_ref(),
+                util::ExtendedKeyUsage::TimeStamping.as_ref(),
+                util::ExtendedKeyUsage::OcspSigning.as_ref(),
+            ]),
+    ];
+
+    let path_len = Arg::new(Parameters::path_len.as_ref())
+        .long(Parameters::path_len.as_ref())
+        .takes_value(true)
+        .value_name("length")
+        .help("Path length constraint of the application CA. Unconstrained by default.")
+        .validator(|n| n.parse::<u8>().map(|_| ()));
+
+    let export_format = Arg::new(Parameters::format.as_ref())
+        .long(Parameters::format.as_ref())
+        .takes_value(true)
+        .default_value(util::ExportFormat::pem.as_ref())
+        .possible_values([
+            util::ExportFormat::pem.as_ref(),
+            util::ExportFormat::der.as_ref(),
+            util::ExportFormat::p12.as_ref(),
+        ])
+        .requires_if(
+            util::ExportFormat::p12.as_ref(),
+            Parameters::key_passphrase.as_ref(),
+        )
+        .help("Format of the written certificate and private key.")
+        .long_help(
+            "Format of the written certificate and private key. \
+            p12 writes a single password protected PKCS#12 bundle containing the certificate, \
+            its private key and the CA certificate, to the --cert_output file for a device, \
+            or to the --key-output file for an application.",
+        );
+
+    let key_passphrase = Arg::new(Parameters::key_passphrase.as_ref())
+        .long(Parameters::key_passphrase.as_ref())
+        .takes_value(false)
+        .help("Encrypt the private key (PKCS#8) or the PKCS#12 bundle with a passphrase, prompted for or read from the DRG_EXPORT_PASSPHRASE environment variable.");
+
+    let key_passphrase_stdin = Arg::new(Parameters::key_passphrase_stdin.as_ref())
+        .long(Parameters::key_passphrase_stdin.as_ref())
+        .takes_value(false)
+        .requires(Parameters::key_passphrase.as_ref())
+        .help("Read the --key-passphrase from the first line of the standard input.");
+
     let access_token_description = Arg::new(Parameters::description.as_ref())
         .long(Parameters::description.as_ref())
         .help("Description to attach to the access token.")
@@ -303,9 +586,20 @@ pub fn app_arguments() -> clap::Command<'static> {
                 .about("Create a trust-anchor for an application.")
                 .arg(&app_flag)
                 .arg(&key_pair_algorithm)
+                .arg(&rsa_bits)
                 .arg(&cert_valid_days)
                 .arg(&key_input)
-                .arg(&keyout),
+                .arg(&keyout)
+                .args(&issuer_args)
+                .group(ArgGroup::new("issuer").args(&[
+                    Parameters::issuer_key.as_ref(),
+                    Parameters::import_signed.as_ref(),
+                ]))
+                .args(&cert_profile_args)
+                .arg(&path_len)
+                .arg(&export_format)
+                .arg(&key_passphrase)
+                .arg(&key_passphrase_stdin),
         )
         .subcommand(
             Command::new(ResourceType::device_cert.as_ref())
@@ -316,8 +610,14 @@ pub fn app_arguments() -> clap::Command<'static> {
                 .arg(&cert_out)
                 .arg(&keyout)
                 .arg(&key_pair_algorithm)
+                .arg(&rsa_bits)
                 .arg(&cert_valid_days)
-                .arg(&key_input),
+                .arg(&key_input)
+                .arg(&csr)
+                .args(&cert_profile_args)
+                .arg(&export_format)
+                .arg(&key_passphrase)
+                .arg(&key_passphrase_stdin),
         )
         .subcommand(
             Command::new(ResourceType::token.as_ref())
@@ -404,6 +704,19 @@ pub fn app_arguments() -> clap::Command<'static> {
         .multiple_values(true)
         .help("A comma separated list of the label filters to filter the list with.");
 
+    let older_than = Arg::new(Parameters::older_than.as_ref())
+        .long(Parameters::older_than.as_ref())
+        .takes_value(true)
+        .help("Only select the tokens created more than this long ago, e.g. 90d.")
+        .validator(|d| humantime::parse_duration(d).map(|_| ()));
+
+    let description_match = Arg::new(Parameters::description_match.as_ref())
+        .long(Parameters::description_match.as_ref())
+        .alias("match")
+        .takes_value(true)
+        .help("Only select the tokens with a description matching this regular expression.")
+        .validator(|r| regex::Regex::new(r).map(|_| ()));
+
     // get subcommand
     let get = Command::new(Action::get.as_ref())
         .about("Display one or multiple resources from the drogue-cloud registry")
@@ -433,6 +746,23 @@ pub fn app_arguments() -> clap::Command<'static> {
             Command::new(ResourceType::token.as_ref())
                 .alias("tokens")
                 .about("List created access tokens for this account")
+                .arg(&older_than)
+                .arg(&description_match)
+        )
+        .subcommand(
+            Command::new(ResourceType::device_cert.as_ref())
+                .alias("device-certs")
+                .about("List the device certificates issued with this context for the application")
+                .arg(&app_flag)
+        )
+        .subcommand(
+            Command::new(ResourceType::transfer.as_ref())
+                .alias("transfers")
+                .about("List the pending transfers of the applications the user has access to, initiated by or offered to the user")
+                .long_about(
+                    "List the pending transfers of the applications the user has access to, initiated by or offered to the user. \
+                    A transfer offered on an application the user cannot list yet is not shown, use `drg transfer status <app>` to check it.",
+                )
         );
 
     let ignore_missing = Arg::new(Parameters::ignore_missing.as_ref())
@@ -442,7 +772,14 @@ pub fn app_arguments() -> clap::Command<'static> {
         .help("Silence the error if the resource does not exist.");
 
     let token_prefix = Arg::new(ResourceId::tokenPrefix.as_ref())
-        .required(true)
+        .required_unless_present_any([
+            Parameters::older_than.as_ref(),
+            Parameters::description_match.as_ref(),
+        ])
+        .conflicts_with_all(&[
+            Parameters::older_than.as_ref(),
+            Parameters::description_match.as_ref(),
+        ])
         .help("The token prefix.");
 
     // delete subcommand
@@ -471,8 +808,22 @@ pub fn app_arguments() -> clap::Command<'static> {
         )
         .subcommand(
             Command::new(ResourceType::token.as_ref())
-                .about("Delete an API access token")
-                .arg(&token_prefix),
+                .alias("tokens")
+                .about("Delete an API access token, or all the tokens selected by the filters")
+                .long_about(
+                    "Delete an API access token, or all the tokens selected by the filters. \
+                    The token the context is authenticated with is never deleted.",
+                )
+                .arg(&token_prefix)
+                .arg(&older_than)
+                .arg(&description_match)
+                .arg(
+                    Arg::new(Parameters::yes.as_ref())
+                        .long(Parameters::yes.as_ref())
+                        .short('y')
+                        .takes_value(false)
+                        .help("Delete the selected tokens without asking for confirmation."),
+                ),
         );
 
     let username = Arg::new(ResourceId::username.as_ref())
@@ -480,25 +831,33 @@ pub fn app_arguments() -> clap::Command<'static> {
         .required(true);
 
     // transfer subcommand
-    let transfer = Command::new(Action::transfer.as_ref())
-        .about("Transfer ownership of an application to another member")
-        .arg_required_else_help(true)
-        .subcommand(
-            Command::new(Transfer::init.as_ref())
-                .about("Initiate the application transfer")
-                .arg(&app_flag)
-                .arg(&username),
-        )
-        .subcommand(
-            Command::new(Transfer::accept.as_ref())
-                .about("Accept an application transfer")
-                .arg(app_id.clone().required(true)),
-        )
-        .subcommand(
-            Command::new(Transfer::cancel.as_ref())
-                .about("Cancel an application transfer")
-                .arg(app_id.clone().required(true)),
-        );
+    let transfer =
+        Command::new(Action::transfer.as_ref())
+            .about("Transfer ownership of an application to another member")
+            .arg_required_else_help(true)
+            .subcommand(
+                Command::new(Transfer::init.as_ref())
+                    .about("Initiate the application transfer")
+                    .arg(&app_flag)
+                    .arg(&username),
+            )
+            .subcommand(
+                Command::new(Transfer::accept.as_ref())
+                    .about("Accept an application transfer")
+                    .arg(app_id.clone().required(true).help(
+                        "The id of the application, or the console link shared by its owner.",
+                    )),
+            )
+            .subcommand(
+                Command::new(Transfer::cancel.as_ref())
+                    .about("Cancel an application transfer")
+                    .arg(app_id.clone().required(true)),
+            )
+            .subcommand(
+                Command::new(Transfer::status.as_ref())
+                    .about("Show the pending transfer of an application")
+                    .arg(app_id.clone().required(true)),
+            );
 
     let gateway_id = Arg::new(ResourceId::gatewayId.as_ref())
         .required(true)
@@ -613,6 +972,12 @@ pub fn app_arguments() -> clap::Command<'static> {
                         .long("active")
                         .takes_value(false)
                         .help("Show only the current active context"),
+                )
+                .arg(
+                    Arg::new(Parameters::show_secrets.as_ref())
+                        .long(Parameters::show_secrets.as_ref())
+                        .takes_value(false)
+                        .help("Show the tokens instead of redacting them, decrypting them if needed."),
                 ),
         )
         .subcommand(
@@ -644,7 +1009,133 @@ pub fn app_arguments() -> clap::Command<'static> {
         .subcommand(
             Command::new("default-algo")
                 .about("Set a default key generation algorithm for a context.")
-                .arg(&algo_param),
+                .arg(&algo_param)
+                .arg(&rsa_bits),
+        )
+        .subcommand(
+            Command::new("key-policy")
+                .about("Restrict the key algorithms allowed with a context.")
+                .long_about(
+                    "Restrict the key algorithms allowed with a context, for the generated keys \
+                    as well as for the keys given as input, signed from a CSR or renewed. \
+                    Running it without any option clears the policy.",
+                )
+                .arg(
+                    Arg::new(Parameters::allow_algo.as_ref())
+                        .long(Parameters::allow_algo.as_ref())
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .use_value_delimiter(true)
+                        .value_name("algo")
+                        .possible_values([
+                            util::SignAlgo::ECDSA.as_ref(),
+                            util::SignAlgo::ECDSA384.as_ref(),
+                            util::SignAlgo::EdDSA.as_ref(),
+                            util::SignAlgo::RSA.as_ref(),
+ util::SignAlgo::RSAPSS.as_ref(),
+                        ])
+                        .help("Allowed algorithm. Can be repeated or comma separated. [default: all]"),
+                )
+                .arg(
+                    Arg::new(Parameters::min_rsa_bits.as_ref())
+                        .long(Parameters::min_rsa_bits.as_ref())
+                        .takes_value(true)
+                        .value_name("bits")
+                        .help("Minimum size of RSA keys.")
+                        .validator(|n| n.parse::<usize>().map(|_| ())),
+                ),
+        )
+        .subcommand(
+            Command::new("cert-defaults")
+                .about("Set the default subject and extensions of the certificates generated with a context.")
+                .long_about(
+                    "Set the default subject and extensions of the certificates generated with a context. \
+                    These are overridden by the options given to create app-cert and create device-cert. \
+                    Running it without any option clears the defaults.",
+                )
+                .args(&cert_profile_args)
+                .arg(&path_len),
+        )
+        .subcommand(
+            Command::new("set-context")
+                .about("Create or update a context, using the cluster and the user of other contexts.")
+                .long_about(
+                    "Create or update a context, using the cluster and the user of other contexts. \
+                    This allows using several identities with the same drogue cloud, or the same identity \
+                    with several drogue cloud instances sharing an SSO server, without logging in again. \
+                    The clusters and users are listed by `drg config show`.",
+                )
+                .arg(&context_id)
+                .arg(
+                    Arg::new(Parameters::cluster.as_ref())
+                        .long(Parameters::cluster.as_ref())
+                        .takes_value(true)
+                        .help("The cluster of the context. Required for a new context."),
+                )
+                .arg(
+                    Arg::new(Parameters::user.as_ref())
+                        .long(Parameters::user.as_ref())
+                        .takes_value(true)
+                        .help("The user the context is logged in as."),
+                )
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("encrypt-tokens")
+                .about("Encrypt the tokens saved in the configuration file with a passphrase.")
+                .long_about(
+                    "Encrypt the tokens saved in the configuration file with a passphrase. \
+                    The passphrase is read from the DRG_CONFIG_PASSPHRASE environment variable, \
+                    or prompted for whenever the tokens of a context are used.",
+                ),
+        )
+        .subcommand(
+            Command::new("decrypt-tokens")
+                .about("Save the tokens in the configuration file in clear text again."),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Export a context, to share it with other users.")
+                .long_about(
+                    "Export a context, to share it with other users. The export holds the drogue cloud URL, \
+                    the endpoints discovered when logging in, the default application and algorithm \
+                    and the certificate settings of the context, but not its credentials unless --with-secrets is given. \
+                    It is imported with `drg config import`, as well as the JSON written with -o json.",
+                )
+                .arg(&context_id)
+                .arg(
+                    Arg::new(Parameters::with_secrets.as_ref())
+                        .long(Parameters::with_secrets.as_ref())
+                        .takes_value(false)
+                        .help("Include the token of the context, decrypting it if needed."),
+                )
+                .arg(
+                    Arg::new(Parameters::base64.as_ref())
+                        .long(Parameters::base64.as_ref())
+                        .takes_value(false)
+                        .help("Encode the export in base64, to paste it as a single line."),
+                ),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Import a context exported with `drg config export`.")
+                .long_about(
+                    "Import a context exported with `drg config export`, as YAML or base64. \
+                    When the export does not hold credentials, drg logs in to the drogue cloud instance. \
+                    The context keeps its exported name, unless another one is given with --context.",
+                )
+                .arg(
+                    Arg::new(Parameters::filename.as_ref())
+                        .required(true)
+                        .value_name("FILE")
+                        .help("The exported context, `-` reads it from the standard input."),
+                )
+                .arg(
+                    Arg::new(Parameters::device_code.as_ref())
+                        .long(Parameters::device_code.as_ref())
+                        .takes_value(false)
+                        .help("Log in with a code entered in a browser on another device, instead of opening a local browser."),
+                ),
         );
 
     let json_apply_path = Arg::new(ResourceType::path.as_ref())
@@ -657,8 +1148,89 @@ pub fn app_arguments() -> clap::Command<'static> {
 
     let apply = Command::new(Action::apply.as_ref())
         .about("Apply a configuration to a device or application through a JSON file. This resource will be created if it doesn't exist yet.")
+        .long_about(
+            "Apply a configuration to a device or application through a JSON file. This resource will be created if it doesn't exist yet. \
+            Members documents, giving the role of each user by application, are applied as well.",
+        )
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
         .arg(&ignore_conflict)
-        .arg(&json_apply_path);
+        .arg(&json_apply_path)
+        .subcommand(
+            Command::new(ResourceType::member.as_ref())
+                .alias("members")
+                .about("Set the members of applications from members documents, showing the role changes.")
+                .arg(
+                    json_apply_path
+                        .clone()
+                        .help("Relative paths to members documents, or to a directory containing them."),
+                )
+                .arg(
+                    Arg::new(Parameters::prune.as_ref())
+                        .long(Parameters::prune.as_ref())
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Remove the members not listed from the applications of the documents."),
+                ),
+        );
+
+    let renew = Command::new(Action::renew.as_ref())
+        .about("Renew certificates")
+        .arg_required_else_help(true)
+        .subcommand(
+            Command::new(ResourceType::device_cert.as_ref())
+                .about("Issue a new certificate for the key of the last certificate issued to a device.")
+                .long_about(
+                    "Issue a new certificate for the key of the last certificate issued to a device \
+                    with this context. The previous certificate is marked as superseded in the local index.",
+                )
+                .arg(&device_id.clone().required(true))
+                .arg(&app_flag)
+                .arg(&ca_key)
+                .arg(&cert_out)
+                .arg(&cert_valid_days)
+                .arg(
+                    Arg::new(Parameters::format.as_ref())
+                        .long(Parameters::format.as_ref())
+                        .takes_value(true)
+                        .default_value(util::ExportFormat::pem.as_ref())
+                        .possible_values([
+                            util::ExportFormat::pem.as_ref(),
+                            util::ExportFormat::der.as_ref(),
+                        ])
+                        .help("Format of the written certificate."),
+                ),
+        );
+
+    let audit = Command::new(Action::audit.as_ref())
+        .about("Audit resources in drogue cloud")
+        .arg_required_else_help(true)
+        .subcommand(
+            Command::new(ResourceType::certs.as_ref())
+                .alias("cert")
+                .about("List the certificates expiring soon in the applications trust anchors.")
+                .long_about(
+                    "List the certificates expiring soon in the trust anchors of all the applications \
+                    visible to this context, and optionally in a local directory of issued certificates. \
+                    Exits with code 2 if any certificate expires within the time window.",
+                )
+                .arg(
+                    Arg::new(Parameters::within.as_ref())
+                        .long(Parameters::within.as_ref())
+                        .takes_value(true)
+                        .default_value("30d")
+                        .value_name("duration")
+                        .help("Report certificates expiring within this time window, e.g. 30d or 12h.")
+                        .validator(|d| humantime::parse_duration(d).map(|_| ())),
+                )
+                .arg(
+                    Arg::new(Parameters::issued_certs.as_ref())
+                        .long(Parameters::issued_certs.as_ref())
+                        .takes_value(true)
+                        .value_name("path/to/dir")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("A local directory containing issued certificates to audit as well."),
+                ),
+        );
 
     let command = Arg::new(Parameters::command.as_ref())
         .required(true)
@@ -695,7 +1267,12 @@ pub fn app_arguments() -> clap::Command<'static> {
         .takes_value(true)
         .global(true)
         .value_name("FILE")
-        .help("Path to the drg config file. If not specified, reads $DRGCFG environment variable or defaults to XDG config directory for drg_config.json");
+        .help("Path to the drg config file. If not specified, reads $DRGCFG environment variable or defaults to XDG config directory for drg_config.json")
+        .long_help(
+            "Path to the drg config file. If not specified, reads $DRGCFG environment variable or defaults to XDG config directory for drg_config.json. \
+            Several files can be given, separated by ':' (';' on Windows): their contexts are merged, the first file defining a context wins. \
+            A context is saved in the file it comes from, unless that file is read-only. New contexts and the active context are saved in the first file.",
+        );
 
     let verbose = Arg::new(Parameters::verbose.as_ref())
         .short('v')
@@ -716,6 +1293,66 @@ pub fn app_arguments() -> clap::Command<'static> {
         .short('k')
         .help("Do not activate the new context.");
 
+    let device_code_arg = Arg::new(Parameters::device_code.as_ref())
+        .long(Parameters::device_code.as_ref())
+        .takes_value(false)
+        .conflicts_with_all(&[Parameters::token.as_ref(), Parameters::access_token.as_ref()])
+        .help("Authenticate with a code entered in a browser on another device, instead of opening a local browser.");
+
+    let client_id_arg = Arg::new(Parameters::client_id.as_ref())
+        .long(Parameters::client_id.as_ref())
+        .takes_value(true)
+        .conflicts_with(Parameters::access_token.as_ref())
+        .help(
+            "The OpenID client to authenticate with, saved in the context. Defaults to \"drogue\".",
+        );
+
+    let client_secret_file_arg = Arg::new(Parameters::client_secret_file.as_ref())
+        .long(Parameters::client_secret_file.as_ref())
+        .takes_value(true)
+        .value_name("FILE")
+        .requires(Parameters::client_id.as_ref())
+        .conflicts_with_all(&[Parameters::token.as_ref(), Parameters::device_code.as_ref()])
+        .help("File containing the client secret. Authenticates as a service account, with the OAuth2 client credentials grant.");
+
+    let scope_arg = Arg::new(Parameters::scope.as_ref())
+        .long(Parameters::scope.as_ref())
+        .takes_value(true)
+        .multiple_occurrences(true)
+        .use_value_delimiter(true)
+        .conflicts_with(Parameters::access_token.as_ref())
+        .help("Additional OpenID scopes to request, saved in the context.");
+
+    let callback_host_arg = Arg::new(Parameters::callback_host.as_ref())
+        .long(Parameters::callback_host.as_ref())
+        .takes_value(true)
+        .conflicts_with_all(&[
+            Parameters::token.as_ref(),
+            Parameters::access_token.as_ref(),
+            Parameters::device_code.as_ref(),
+            Parameters::client_secret_file.as_ref(),
+        ])
+        .validator(|h| h.parse::<std::net::IpAddr>().map(|_| ()))
+        .help("The IP address the browser login callback listens on, saved in the context. Defaults to 127.0.0.1.");
+
+    let callback_port_arg = Arg::new(Parameters::callback_port.as_ref())
+        .long(Parameters::callback_port.as_ref())
+        .takes_value(true)
+        .conflicts_with_all(&[
+            Parameters::token.as_ref(),
+            Parameters::access_token.as_ref(),
+            Parameters::device_code.as_ref(),
+            Parameters::client_secret_file.as_ref(),
+        ])
+        .validator(|p| p.parse::<u16>().map(|_| ()))
+        .help("A fixed port for the browser login callback, saved in the context. Defaults to a random port.");
+
+    let create_access_token_arg = Arg::new(Parameters::create_access_token.as_ref())
+        .long(Parameters::create_access_token.as_ref())
+        .takes_value(false)
+        .conflicts_with(Parameters::access_token.as_ref())
+        .help("Create an API access token once logged in, and save it in the context instead of the OpenID tokens.");
+
     let interactive = Arg::new(Parameters::interactive.as_ref())
         .long(Parameters::interactive.as_ref())
         .takes_value(false)
@@ -742,6 +1379,22 @@ pub fn app_arguments() -> clap::Command<'static> {
         .subcommand(config)
         .subcommand(transfer)
         .subcommand(label)
+        .subcommand(audit)
+        .subcommand(renew)
+        .subcommand(
+            Command::new(Action::rotate.as_ref())
+                .about("Rotate credentials")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new(ResourceType::token.as_ref())
+                        .about("Replace the access token of a context with a new one, and delete the previous token.")
+                        .long_about(
+                            "Replace the access token of a context with a new one, and delete the previous token. \
+                            The new token is validated and saved before the previous one is deleted, \
+                            if it is rejected the context keeps the previous token.",
+                        ),
+                ),
+        )
         .subcommand(
             Command::new(Action::command.as_ref())
                 .alias("cmd")
@@ -769,7 +1422,39 @@ pub fn app_arguments() -> clap::Command<'static> {
                 .arg(&access_token_arg)
                 .about("Log into a drogue cloud installation.")
                 .arg(&url)
-                .arg(&login_keep_current),
+                .arg(&login_keep_current)
+                .arg(&device_code_arg)
+                .arg(&client_id_arg)
+                .arg(&client_secret_file_arg)
+                .arg(&scope_arg)
+                .arg(&callback_host_arg)
+                .arg(&callback_port_arg)
+                .arg(&create_access_token_arg)
+                .arg(
+                    access_token_description
+                        .clone()
+                        .requires(Parameters::create_access_token.as_ref()),
+                ),
+        )
+        .subcommand(
+            Command::new(Action::logout.as_ref())
+                .about("Log out of a drogue cloud installation.")
+                .long_about(
+                    "Log out of a drogue cloud installation. The OpenID tokens of the context are \
+                    revoked on the SSO server and removed from the context, which is kept unless --purge is given.",
+                )
+                .arg(
+                    Arg::new(Parameters::all.as_ref())
+                        .long(Parameters::all.as_ref())
+                        .takes_value(false)
+                        .help("Log out of all the contexts."),
+                )
+                .arg(
+                    Arg::new(Parameters::purge.as_ref())
+                        .long(Parameters::purge.as_ref())
+                        .takes_value(false)
+                        .help("Remove the context from the configuration file."),
+                ),
         )
         .subcommand(
             Command::new(Action::whoami.as_ref())
@@ -777,6 +1462,27 @@ pub fn app_arguments() -> clap::Command<'static> {
                 .arg(token_arg.clone().takes_value(false).help(
                     "Pulls an valid token from the context to authenticate against drogue cloud.",
                 ))
+                .arg(
+                    Arg::new(Parameters::claims.as_ref())
+                        .long(Parameters::claims.as_ref())
+                        .takes_value(false)
+                        .conflicts_with(Parameters::token.as_ref())
+                        .help("Show the identity, roles and token expiry decoded from the context tokens."),
+                )
+                .arg(
+                    Arg::new(Parameters::permissions.as_ref())
+                        .long(Parameters::permissions.as_ref())
+                        .takes_value(false)
+                        .conflicts_with_all(&[Parameters::token.as_ref(), Parameters::claims.as_ref()])
+                        .help("List the role of the user on every application visible to the context. A drogue cloud administrator shows as the owner of every application."),
+                )
+                .arg(
+                    Arg::new(Parameters::user.as_ref())
+                        .long(Parameters::user.as_ref())
+                        .takes_value(true)
+                        .requires(Parameters::permissions.as_ref())
+                        .help("Give the permissions of another user, on the applications whose members the context can read."),
+                )
                 .subcommand(
                     Command::new(Parameters::endpoints.as_ref())
                         .about("List drogue-cloud available endpoints.")
@@ -1,13 +1,17 @@
-use crate::util::DrogueError;
+use crate::config::{ExportedContext, Token};
+use crate::openid::{self, LoginMethod};
+use crate::util::{show_json, DrogueError};
 use crate::{
-    config::pretty_list, display, display_simple, util, Config, Outcome, Parameters, ResourceId,
+    arguments, config::pretty_list, display, display_simple, Config, Outcome, Parameters,
+    ResourceId,
 };
 use anyhow::Result;
 
 use clap::ArgMatches;
-use std::str::FromStr;
+use std::fs;
+use std::io::Read;
 
-pub fn subcommand(
+pub async fn subcommand(
     matches: &ArgMatches,
     config: &mut Config,
     ctx_name: &Option<String>,
@@ -26,17 +30,30 @@ pub fn subcommand(
             pretty_list(c, config.active_context.as_ref())
         }),
         "show" => {
+            let show_secrets = c.is_present(Parameters::show_secrets.as_ref());
             if c.is_present("active") {
-                let c = config
-                    .get_context(ctx_name)
-                    .map(Outcome::SuccessWithJsonData)
-                    .map_err(|e| DrogueError::ConfigIssue(e.to_string()));
+                let c = if show_secrets {
+                    config.unlock_context(ctx_name).map(|c| c.clone())
+                } else {
+                    config.get_context(ctx_name).map(|c| c.redacted())
+                }
+                .map(Outcome::SuccessWithJsonData)
+                .map_err(|e| DrogueError::ConfigIssue(format!("{:#}", e)));
 
                 display(c, json, |c| println!("{}", c))
-            } else {
+            } else if show_secrets {
+                config
+                    .unlock_all()
+                    .map_err(|e| DrogueError::ConfigIssue(format!("{:#}", e)))?;
                 display(Ok(Outcome::SuccessWithJsonData(config)), json, |c| {
                     println!("{}", c)
                 })
+            } else {
+                display(
+                    Ok(Outcome::SuccessWithJsonData(config.redacted())),
+                    json,
+                    |c| println!("{}", c),
+                )
             }
         }
         "default-context" => {
@@ -66,17 +83,131 @@ pub fn subcommand(
             )
         }
         "default-algo" => {
-            let algo = c
-                .value_of(&Parameters::algo.as_ref())
-                .map(|a| util::SignAlgo::from_str(a).unwrap())
-                .unwrap();
+            // Safe unwrap because clap makes sure the algorithm is provided
+            let algo = arguments::sign_algo_from_args(c)?.unwrap();
             let context = config.get_context_mut(ctx_name)?;
             let outcome = context.set_default_algo(algo);
+            config.changed(outcome.is_ok());
+            display_simple(outcome, json)
+        }
+        "key-policy" => {
+            let policy = arguments::key_policy_from_args(c)?;
+            let context = config.get_context_mut(ctx_name)?;
+            let outcome = context.set_key_policy(policy);
+            config.changed(true);
+            display_simple(Ok(outcome), json)
+        }
+        "cert-defaults" => {
+            let profile = arguments::cert_profile_from_args(c)?;
+            let context = config.get_context_mut(ctx_name)?;
+            let outcome = context.set_cert_profile(profile);
             config.changed(true);
             display_simple(Ok(outcome), json)
         }
+        "set-context" => {
+            let cluster = c.value_of(Parameters::cluster.as_ref());
+            let user = c.value_of(Parameters::user.as_ref());
+            display_simple(
+                config.set_context(ctx_name.as_ref().unwrap(), cluster, user),
+                json,
+            )
+        }
+        "encrypt-tokens" => display_simple(config.encrypt_tokens(), json),
+        "decrypt-tokens" => display_simple(config.decrypt_tokens(), json),
+        "export" => {
+            let with_secrets = c.is_present(Parameters::with_secrets.as_ref());
+            let base64 = c.is_present(Parameters::base64.as_ref());
+            if json && base64 {
+                return Err(DrogueError::InvalidInput(
+                    "--base64 cannot be used with -o json".to_string(),
+                )
+                .into());
+            }
+            let exported = config
+                .export_context(ctx_name, with_secrets)
+                .map_err(|e| DrogueError::ConfigIssue(format!("{:#}", e)))?;
+
+            if with_secrets {
+                eprintln!("The export holds the credentials of the context, keep it private.");
+            }
+            if json {
+                show_json(&serde_json::to_value(&exported)?);
+            } else {
+                let encoded = exported
+                    .encode(base64)
+                    .map_err(|e| DrogueError::ConfigIssue(format!("{:#}", e)))?;
+                println!("{}", encoded.trim_end());
+            }
+            Ok(0)
+        }
+        "import" => {
+            // Safe unwrap because clap makes sure the file is provided
+            let file = c.value_of(Parameters::filename.as_ref()).unwrap();
+            let login_method = if c.is_present(Parameters::device_code.as_ref()) {
+                LoginMethod::DeviceCode
+            } else {
+                LoginMethod::Browser
+            };
+            display_simple(import(config, file, ctx_name, login_method).await, json)
+        }
         _ => {
             unreachable!("forgot to route config subcommand : {}", v);
         }
     }
 }
+
+// Add an exported context, logging in when the export holds no credentials.
+async fn import(
+    config: &mut Config,
+    file: &str,
+    ctx_name: &Option<String>,
+    login_method: LoginMethod<'_>,
+) -> Result<Outcome<String>, DrogueError> {
+    let mut content = String::new();
+    if file == "-" {
+        std::io::stdin().read_to_string(&mut content)
+    } else {
+        fs::File::open(file).and_then(|mut f| f.read_to_string(&mut content))
+    }
+    .map_err(|e| DrogueError::InvalidInput(format!("Cannot read {}: {}", file, e)))?;
+
+    let exported = ExportedContext::decode(&content)
+        .map_err(|e| DrogueError::InvalidInput(format!("Invalid export {}: {}", file, e)))?;
+    let name = ctx_name.clone().unwrap_or_else(|| exported.name.clone());
+    if config.contains_context(&name) {
+        return Err(DrogueError::InvalidInput(format!(
+            "Context {} already exists, give another name with --context",
+            name
+        )));
+    }
+
+    let mut context = exported.into_context(name.clone());
+    let mut message = format!("Imported context: {}", name);
+    if matches!(context.token, Token::LoggedOut) {
+        let logged_in = openid::login(
+            context.drogue_cloud_url.clone(),
+            login_method,
+            context.oidc.clone().unwrap_or_default(),
+            name.clone(),
+        )
+        .await
+        .map_err(|e| DrogueError::InvalidInput(format!("{e}")))?;
+
+        context.fill_urls(
+            logged_in.auth_url,
+            logged_in.registry_url,
+            logged_in.token_url,
+        );
+        context.token = logged_in.token;
+        context.token_exp_date = logged_in.token_exp_date;
+        message = format!(
+            "{}\nSuccessfully authenticated to drogue cloud : {}",
+            message, context.drogue_cloud_url
+        );
+    }
+
+    config
+        .add_context(context)
+        .map_err(|e| DrogueError::ConfigIssue(e.to_string()))?;
+    Ok(Outcome::SuccessWithMessage(message))
+}
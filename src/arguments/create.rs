@@ -1,6 +1,6 @@
 use crate::{
     admin, arguments, display, display_simple, tokens, util, ApplicationOperation, Context,
-    DeviceOperation, Parameters, ResourceId, ResourceType,
+    DeviceOperation, DrogueError, Parameters, ResourceId, ResourceType,
 };
 use anyhow::{anyhow, Result};
 use clap::ArgMatches;
@@ -38,10 +38,10 @@ pub async fn subcommand(matches: &ArgMatches, context: &Context, json_output: bo
 
             // add an alias with the correct subject dn.
             if command.is_present(Parameters::cert.as_ref()) {
-                let alias = format!(
-                    "CN={}, O=Drogue IoT, OU={}",
+                let alias = util::device_alias(
                     &util::name_from_json_or_file(dev_id.clone(), file)?,
-                    app_id
+                    &app_id,
+                    &context.cert_profile(Default::default()),
                 );
                 let alias_spec = json!([alias]);
 
@@ -82,30 +82,100 @@ pub async fn subcommand(matches: &ArgMatches, context: &Context, json_output: bo
         ResourceType::app_cert | ResourceType::device_cert => {
             let app_id = arguments::get_app_id(command, context)?;
             let days = command.value_of(&Parameters::days.as_ref());
-            let key_pair_algorithm = command
-                .value_of(&Parameters::algo.as_ref())
-                .or_else(|| {
-                    context.default_algo.as_deref().map(|a| {
+            let key_pair_algorithm = match arguments::sign_algo_from_args(command)? {
+                Some(algo) => Some(algo),
+                None => context
+                    .default_algo
+                    .as_deref()
+                    .map(|a| {
                         log::debug!("Using default signature algorithm: {}", a);
-                        a
+                        util::SignAlgo::from_str(a)
                     })
-                })
-                .map(|algo| util::SignAlgo::from_str(algo).unwrap());
+                    .transpose()?,
+            };
 
+            let policy = context.key_policy();
             let (key_input, key_pair_algorithm) =
                 match command.value_of(&Parameters::key_input.as_ref()) {
-                    Some(f) => util::verify_input_key(f).map(|s| (Some(s.0), Some(s.1)))?,
-                    _ => (None, key_pair_algorithm),
+                    Some(f) => {
+                        let (key, algo) = util::verify_input_key(f)?;
+                        // An RSA key can make RSA-PSS signatures as well
+                        let algo = match key_pair_algorithm {
+                            Some(util::SignAlgo::RSAPSS) if algo.rsa_bits().is_some() => {
+                                util::SignAlgo::RSAPSS
+                            }
+                            _ => algo,
+                        };
+                        let bits = match algo.rsa_bits() {
+                            Some(_) => Some(util::rsa_key_bits(&key)?),
+                            None => None,
+                        };
+                        policy.check(algo, bits)?;
+                        (Some(key), Some(algo))
+                    }
+                    // The key of a CSR is generated by the device, it is checked once the CSR is read
+                    _ if command.value_of(Parameters::csr.as_ref()).is_some() => {
+                        (None, key_pair_algorithm)
+                    }
+                    _ => {
+                        policy.check(key_pair_algorithm.unwrap_or(util::SignAlgo::ECDSA), None)?;
+                        (None, key_pair_algorithm)
+                    }
                 };
 
             let keyout = command.value_of(&Parameters::key_output.as_ref());
 
             let device_key = command.value_of(&Parameters::key_output.as_ref());
+            let profile = context.cert_profile(arguments::cert_profile_from_args(command)?);
+            let export = arguments::export_options_from_args(command)?;
 
             if resource == ResourceType::app_cert {
+                let issuer_cert = command.value_of(Parameters::issuer_cert.as_ref());
+
+                if let Some(csr_out) = command.value_of(Parameters::csr_output.as_ref()) {
+                    return display_simple(
+                        util::create_trust_anchor_request(
+                            &app_id,
+                            keyout,
+                            key_pair_algorithm,
+                            key_input,
+                            &profile,
+                            &export,
+                            csr_out,
+                        )
+                        .map_err(|e| DrogueError::InvalidInput(e.to_string())),
+                        json_output,
+                    );
+                }
+
+                let trust_anchor = match command.value_of(Parameters::import_signed.as_ref()) {
+                    // Safe unwrap because clap makes sure --key-input is provided
+                    Some(signed) => {
+                        util::import_trust_anchor(signed, key_input.as_ref().unwrap(), issuer_cert)
+                    }
+                    None => util::create_trust_anchor(
+                        &app_id,
+                        keyout,
+                        key_pair_algorithm,
+                        days,
+                        key_input,
+                        &profile,
+                        &export,
+                        command
+                            .value_of(Parameters::issuer_key.as_ref())
+                            .map(|key| util::Issuer {
+                                // Safe unwrap because clap makes sure --issuer-cert is provided
+                                certificate: issuer_cert.unwrap(),
+                                key,
+                            })
+                            .as_ref(),
+                    ),
+                }
+                .map_err(|e| DrogueError::InvalidInput(e.to_string()))?;
+
                 display_simple(
                     ApplicationOperation::new(Some(app_id), None, None)?
-                        .add_trust_anchor(context, keyout, key_pair_algorithm, days, key_input)
+                        .add_trust_anchor(context, trust_anchor)
                         .await,
                     json_output,
                 )
@@ -118,20 +188,41 @@ pub async fn subcommand(matches: &ArgMatches, context: &Context, json_output: bo
                 let cert = ApplicationOperation::new(Some(app_id.clone()), None, None)?
                     .get_trust_anchor(context)
                     .await?;
+                let index = util::CertIndex::for_application(context, &app_id)?;
 
-                match util::create_device_certificate(
-                    &app_id,
-                    dev_id,
-                    ca_key,
-                    cert.anchors[0].certificate.as_slice(),
-                    device_key,
-                    device_cert,
-                    key_pair_algorithm,
-                    days,
-                    key_input,
-                ) {
+                let signed = match command.value_of(Parameters::csr.as_ref()) {
+                    Some(csr) => util::sign_device_csr(
+                        &app_id,
+                        dev_id,
+                        ca_key,
+                        cert.anchors[0].certificate.as_slice(),
+                        csr,
+                        device_cert,
+                        days,
+                        &profile,
+                        &policy,
+                        &export,
+                        &index,
+                    ),
+                    None => util::create_device_certificate(
+                        &app_id,
+                        dev_id,
+                        ca_key,
+                        cert.anchors[0].certificate.as_slice(),
+                        device_key,
+                        device_cert,
+                        key_pair_algorithm,
+                        days,
+                        key_input,
+                        &profile,
+                        &export,
+                        &index,
+                    ),
+                };
+
+                match signed {
                     Ok(_) => {
-                        let alias = format!("CN={}, O=Drogue IoT, OU={}", dev_id, app_id);
+                        let alias = util::device_alias(dev_id, &app_id, &profile);
 
                         display_simple(
                             DeviceOperation::new(app_id, Some(dev_id.to_string()), None, None)?
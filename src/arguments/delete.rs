@@ -1,6 +1,6 @@
 use crate::{
     admin, arguments, display_simple, tokens, ApplicationOperation, Context, DeviceOperation,
-    Parameters, ResourceId, ResourceType,
+    DrogueError, Parameters, ResourceId, ResourceType,
 };
 use anyhow::Result;
 use clap::ArgMatches;
@@ -49,8 +49,24 @@ pub async fn subcommand(matches: &ArgMatches, context: &Context, json_output: bo
             )
         }
         ResourceType::token => {
-            let prefix = command.value_of(ResourceId::tokenPrefix.as_ref()).unwrap();
-            display_simple(tokens::delete(context, prefix).await, json_output)
+            let outcome = match command.value_of(ResourceId::tokenPrefix.as_ref()) {
+                Some(prefix) => tokens::delete(context, prefix).await,
+                // clap makes sure a filter is given when the prefix is not
+                None => {
+                    let filter = arguments::token_filter_from_args(command)?;
+                    let confirmed = command.is_present(Parameters::yes.as_ref());
+                    if json_output && !confirmed {
+                        // The confirmation prompt would break the JSON output
+                        Err(DrogueError::InvalidInput(
+                            "--yes is required to delete several access tokens with -o json"
+                                .to_string(),
+                        ))
+                    } else {
+                        tokens::prune(context, &filter, confirmed).await
+                    }
+                }
+            };
+            display_simple(outcome, json_output)
         }
         // The other enum variants are not exposed by clap
         _ => unreachable!(),
@@ -1,6 +1,6 @@
 use crate::{
-    admin, applications, arguments, devices, display, tokens, ApplicationOperation, Context,
-    DeviceOperation, Parameters, ResourceId, ResourceType,
+    admin, applications, arguments, devices, display, tokens, util, ApplicationOperation, Context,
+    DeviceOperation, Outcome, Parameters, ResourceId, ResourceType,
 };
 use anyhow::Result;
 use clap::ArgMatches;
@@ -59,10 +59,27 @@ pub async fn subcommand(matches: &ArgMatches, context: &Context, json_output: bo
                 admin::members_table,
             )
         }
-        ResourceType::token => display(
-            tokens::get_api_keys(context).await,
+        ResourceType::token => {
+            let filter = arguments::token_filter_from_args(command)?;
+            display(
+                tokens::list(context, &filter).await,
+                json_output,
+                tokens::tokens_table,
+            )
+        }
+        ResourceType::device_cert => {
+            let app_id = arguments::get_app_id(command, context)?;
+            let issued = util::CertIndex::for_application(context, &app_id)?
+                .list()
+                .map(Outcome::SuccessWithJsonData)
+                .map_err(|e| util::DrogueError::ConfigIssue(e.to_string()));
+
+            display(issued, json_output, util::issued_table)
+        }
+        ResourceType::transfer => display(
+            admin::list_transfers(context).await,
             json_output,
-            tokens::tokens_table,
+            admin::transfers_table,
         ),
         // The other enum variants are not exposed by clap
         _ => unreachable!(),
@@ -1,5 +1,9 @@
-use crate::{openid, util, Config, DrogueError, Outcome, Parameters};
+use crate::config::{AccessToken, Context, Token};
+use crate::openid::{LoginMethod, OidcSettings};
+use crate::{openid, tokens, util, Config, DrogueError, Outcome, Parameters};
+use chrono::{DateTime, Utc};
 use clap::ArgMatches;
+use std::fs;
 
 pub async fn subcommand(
     matches: &ArgMatches,
@@ -21,10 +25,41 @@ pub async fn subcommand(
             ))
         }
     } else {
-        let refresh_token_val = matches.value_of(Parameters::token.as_ref());
-        openid::login(url.clone(), refresh_token_val, ctx_name)
+        // Logging in again keeps the settings of the context, unless they are given again
+        let current = config
+            .get_context(&Some(ctx_name.clone()))
+            .ok()
+            .filter(|c| c.drogue_cloud_url == url)
+            .map(|c| c.oidc())
+            .unwrap_or_default();
+        let settings = oidc_settings_from_args(matches, current);
+        let method = if let Some(refresh_token) = matches.value_of(Parameters::token.as_ref()) {
+            LoginMethod::RefreshToken(refresh_token)
+        } else if let Some(secret_file) = matches.value_of(Parameters::client_secret_file.as_ref())
+        {
+            let client_secret = fs::read_to_string(secret_file).map_err(|e| {
+                DrogueError::InvalidInput(format!("Cannot read {}: {}", secret_file, e))
+            })?;
+            LoginMethod::ClientCredentials {
+                client_id: settings.client_id().to_string(),
+                client_secret: client_secret.trim().to_string(),
+            }
+        } else if matches.is_present(Parameters::device_code.as_ref()) {
+            LoginMethod::DeviceCode
+        } else {
+            LoginMethod::Browser
+        };
+
+        let context = openid::login(url.clone(), method, settings.clone(), ctx_name)
             .await
-            .map_err(|e| DrogueError::InvalidInput(format!("{e}")))
+            .map_err(|e| DrogueError::InvalidInput(format!("{e}")))?;
+
+        if matches.is_present(Parameters::create_access_token.as_ref()) {
+            let description = matches.value_of(Parameters::description.as_ref());
+            access_token_context(context, description).await
+        } else {
+            Ok(context)
+        }
     }?;
 
     let mut message = format!(
@@ -42,3 +77,59 @@ pub async fn subcommand(
 
     Ok(Outcome::SuccessWithMessage(message))
 }
+
+/// The settings given as arguments, the others are taken from `current`.
+fn oidc_settings_from_args(matches: &ArgMatches, current: OidcSettings) -> OidcSettings {
+    OidcSettings {
+        client_id: matches
+            .value_of(Parameters::client_id.as_ref())
+            .map(|c| c.to_string())
+            .or(current.client_id),
+        scopes: matches
+            .values_of(Parameters::scope.as_ref())
+            .map(|scopes| scopes.map(|s| s.to_string()).collect())
+            .unwrap_or(current.scopes),
+        // Safe unwraps because clap validates the host and port
+        callback_host: matches
+            .value_of(Parameters::callback_host.as_ref())
+            .map(|h| h.parse().unwrap())
+            .or(current.callback_host),
+        callback_port: matches
+            .value_of(Parameters::callback_port.as_ref())
+            .map(|p| p.parse().unwrap())
+            .or(current.callback_port),
+    }
+}
+
+/// Replace the OpenID tokens of a freshly logged in context with a new API access token,
+/// which does not expire. The OpenID tokens are revoked, they are not needed anymore.
+async fn access_token_context(
+    mut context: Context,
+    description: Option<&str>,
+) -> Result<Context, DrogueError> {
+    let username = openid::username(&context).ok_or_else(|| {
+        DrogueError::InvalidInput("Cannot read the username from the OpenID token".to_string())
+    })?;
+
+    let token = match tokens::create(&context, description).await? {
+        Outcome::SuccessWithJsonData(token) => token,
+        Outcome::SuccessWithMessage(msg) => {
+            return Err(DrogueError::UnexpectedClient(anyhow::anyhow!(msg)))
+        }
+    };
+    log::info!("Access token {} created", token.prefix);
+
+    if let Err(e) = openid::logout(&mut context).await {
+        log::warn!("Cannot revoke the OpenID tokens of the login: {}", e);
+    }
+
+    // The context keeps its endpoints, OpenID settings and defaults
+    Ok(Context {
+        token: Token::AccessToken(AccessToken {
+            id: username,
+            token: token.token,
+        }),
+        token_exp_date: DateTime::<Utc>::MAX_UTC,
+        ..context
+    })
+}
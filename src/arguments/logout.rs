@@ -0,0 +1,43 @@
+use crate::{openid, Config, DrogueError, Outcome, Parameters};
+use clap::ArgMatches;
+
+pub async fn subcommand(
+    matches: &ArgMatches,
+    config: &mut Config,
+    ctx_name: &Option<String>,
+) -> Result<Outcome<String>, DrogueError> {
+    let names: Vec<String> = if matches.is_present(Parameters::all.as_ref()) {
+        config.contexts.iter().map(|c| c.name.clone()).collect()
+    } else {
+        vec![config.get_context(ctx_name)?.name.clone()]
+    };
+    let purge = matches.is_present(Parameters::purge.as_ref());
+
+    let mut messages = Vec::new();
+    let mut failed = 0;
+    for name in names {
+        let context = config.unlock_context(&Some(name.clone()))?;
+        if let Err(e) = openid::logout(context).await {
+            log::error!("Cannot log out of context {}: {}", name, e);
+            failed += 1;
+            continue;
+        }
+
+        if purge {
+            config.delete_context(&name)?;
+            messages.push(format!("Logged out of context {}, context deleted", name));
+        } else {
+            config.changed(true);
+            messages.push(format!("Logged out of context {}", name));
+        }
+    }
+
+    if failed > 0 {
+        Err(DrogueError::InvalidInput(format!(
+            "Could not log out of {} context(s)",
+            failed
+        )))
+    } else {
+        Ok(Outcome::SuccessWithMessage(messages.join("\n")))
+    }
+}
@@ -5,10 +5,19 @@ pub mod delete;
 pub mod edit;
 pub mod get;
 pub mod login;
+pub mod logout;
+pub mod renew;
 
-use crate::Context;
+use crate::admin::tokens::TokenFilter;
+use crate::util::{
+    read_passphrase, CertificateProfile, ExportFormat, ExportOptions, KeyPolicy, SignAlgo,
+    EXPORT_PASSPHRASE_ENV,
+};
+use crate::{Context, Parameters};
 use anyhow::{anyhow, Result};
 use clap::ArgMatches;
+use std::io::{stdin, BufRead};
+use std::str::FromStr;
 
 pub fn get_app_id<'a>(matches: &'a ArgMatches, config: &'a Context) -> Result<String> {
     match matches.value_of("app-flag") {
@@ -25,3 +34,157 @@ pub fn get_app_id<'a>(matches: &'a ArgMatches, config: &'a Context) -> Result<St
             }),
     }
 }
+
+/// Reads the certificate subject and extensions options. Unset options are left empty.
+pub fn cert_profile_from_args(matches: &ArgMatches) -> Result<CertificateProfile> {
+    let value = |param: Parameters| matches.value_of(param.as_ref()).map(|v| v.to_string());
+
+    fn values<T: FromStr>(matches: &ArgMatches, param: Parameters) -> Result<Vec<T>> {
+        matches
+            .values_of(param.as_ref())
+            .map(|values| {
+                values
+                    .map(|v| {
+                        T::from_str(v)
+                            .map_err(|_| anyhow!("Invalid {} value: {}", param.as_ref(), v))
+                    })
+                    .collect()
+            })
+            .unwrap_or_else(|| Ok(Vec::new()))
+    }
+
+    // --path-len is only available on some commands
+    let path_length = match matches.try_contains_id(Parameters::path_len.as_ref()) {
+        Ok(true) => value(Parameters::path_len).map(|l| l.parse()).transpose()?,
+        _ => None,
+    };
+
+    Ok(CertificateProfile {
+        organization: value(Parameters::subject_org),
+        country: value(Parameters::subject_country),
+        state: value(Parameters::subject_state),
+        locality: value(Parameters::subject_locality),
+        dns_names: values(matches, Parameters::san_dns)?,
+        uris: values(matches, Parameters::san_uri)?,
+        ip_addresses: values(matches, Parameters::san_ip)?,
+        key_usages: values(matches, Parameters::key_usage)?,
+        extended_key_usages: values(matches, Parameters::ext_key_usage)?,
+        path_length,
+    })
+}
+
+/// Reads the output format of certificates and keys, along with the passphrase protecting them.
+pub fn export_options_from_args(matches: &ArgMatches) -> Result<ExportOptions> {
+    let format = matches
+        .value_of(Parameters::format.as_ref())
+        .map(ExportFormat::from_str)
+        .transpose()?
+        .unwrap_or(ExportFormat::pem);
+
+    let passphrase = if matches.is_present(Parameters::key_passphrase_stdin.as_ref()) {
+        Some(read_passphrase_line(&mut stdin().lock())?)
+    } else if matches.is_present(Parameters::key_passphrase.as_ref()) {
+        Some(read_passphrase(
+            EXPORT_PASSPHRASE_ENV,
+            "Passphrase for the written key: ",
+            true,
+        )?)
+    } else {
+        None
+    };
+
+    if passphrase.as_deref() == Some("") {
+        return Err(anyhow!("The key passphrase cannot be empty"));
+    }
+
+    Ok(ExportOptions { format, passphrase })
+}
+
+// Only the line ending is stripped: the passphrase may start or end with spaces
+fn read_passphrase_line<R: BufRead>(reader: &mut R) -> Result<String> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+/// Reads the key algorithm, --rsa-bits selecting the size of RSA keys.
+pub fn sign_algo_from_args(matches: &ArgMatches) -> Result<Option<SignAlgo>> {
+    let algo = matches
+        .value_of(Parameters::algo.as_ref())
+        .map(SignAlgo::from_str)
+        .transpose()?;
+
+    match matches.value_of(Parameters::rsa_bits.as_ref()) {
+        Some(bits) => match algo {
+            None | Some(SignAlgo::RSA) => SignAlgo::rsa(bits.parse()?).map(Some),
+            Some(other) => Err(anyhow!(
+                "--rsa-bits cannot be used with the {} algorithm",
+                other.as_ref()
+            )),
+        },
+        None => Ok(algo),
+    }
+}
+
+pub fn key_policy_from_args(matches: &ArgMatches) -> Result<KeyPolicy> {
+    Ok(KeyPolicy {
+        allowed_algorithms: matches
+            .values_of(Parameters::allow_algo.as_ref())
+            .map(|values| values.map(SignAlgo::from_str).collect())
+            .transpose()?
+            .unwrap_or_default(),
+        min_rsa_bits: matches
+            .value_of(Parameters::min_rsa_bits.as_ref())
+            .map(|bits| bits.parse())
+            .transpose()?,
+    })
+}
+
+pub fn token_filter_from_args(matches: &ArgMatches) -> Result<TokenFilter> {
+    let older_than = match matches.value_of(Parameters::older_than.as_ref()) {
+        Some(d) => Some(chrono::Duration::from_std(humantime::parse_duration(d)?)?),
+        None => None,
+    };
+    let description = match matches.value_of(Parameters::description_match.as_ref()) {
+        Some(r) => Some(regex::Regex::new(r)?),
+        None => None,
+    };
+
+    Ok(TokenFilter {
+        older_than,
+        description,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arguments::cli::app_arguments;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_passphrase_from_stdin() {
+        let mut input = Cursor::new(" very secret \r\nnext line\n");
+        assert_eq!(read_passphrase_line(&mut input).unwrap(), " very secret ");
+
+        let mut input = Cursor::new("no line ending");
+        assert_eq!(read_passphrase_line(&mut input).unwrap(), "no line ending");
+    }
+
+    #[test]
+    fn test_passphrase_stdin_requires_passphrase() {
+        let create = |args: &[&str]| {
+            app_arguments().try_get_matches_from(
+                ["drg", "create", "device-cert", "foo", "--ca-key", "key.pem"]
+                    .iter()
+                    .chain(args),
+            )
+        };
+
+        assert!(create(&["--key-passphrase-stdin"]).is_err());
+        let matches = create(&["--key-passphrase", "--key-passphrase-stdin"]).unwrap();
+        let (_, create) = matches.subcommand().unwrap();
+        let (_, device_cert) = create.subcommand().unwrap();
+        assert!(device_cert.is_present(Parameters::key_passphrase_stdin.as_ref()));
+    }
+}
@@ -0,0 +1,47 @@
+use crate::{
+    arguments, display_simple, util, ApplicationOperation, Context, DrogueError, Parameters,
+    ResourceId, ResourceType,
+};
+use anyhow::Result;
+use clap::ArgMatches;
+use std::str::FromStr;
+
+pub async fn subcommand(matches: &ArgMatches, context: &Context, json_output: bool) -> Result<i32> {
+    let (res, command) = matches.subcommand().unwrap();
+    let resource = ResourceType::from_str(res)?;
+
+    match resource {
+        ResourceType::device_cert => {
+            let app_id = arguments::get_app_id(command, context)?;
+            // Safe unwraps because clap makes sure the arguments are provided
+            let dev_id = command.value_of(ResourceId::deviceId.as_ref()).unwrap();
+            let ca_key = command.value_of(Parameters::ca_key.as_ref()).unwrap();
+            let cert_out = command.value_of(Parameters::cert_output.as_ref());
+            let days = command.value_of(Parameters::days.as_ref());
+            let export = arguments::export_options_from_args(command)?;
+
+            let cert = ApplicationOperation::new(Some(app_id.clone()), None, None)?
+                .get_trust_anchor(context)
+                .await?;
+            let index = util::CertIndex::for_application(context, &app_id)?;
+
+            let renewed = util::renew_device_certificate(
+                &app_id,
+                dev_id,
+                ca_key,
+                cert.anchors[0].certificate.as_slice(),
+                cert_out,
+                days,
+                &context.cert_profile(Default::default()),
+                &context.key_policy(),
+                &export,
+                &index,
+            )
+            .map_err(|e| DrogueError::InvalidInput(format!("Cannot renew certificate: {}", e)));
+
+            display_simple(renewed, json_output)
+        }
+        // The other enum variants are not exposed by clap
+        _ => unreachable!(),
+    }
+}
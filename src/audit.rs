@@ -0,0 +1,195 @@
+use crate::config::Context;
+use crate::util::{DrogueError, Outcome};
+use crate::ApplicationOperation;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use drogue_client::registry::v1::ApplicationSpecTrustAnchors;
+use drogue_client::Translator;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tabular::{Row, Table};
+use x509_parser::pem::Pem;
+
+/// Exit code returned by `drg audit certs` when expiring certificates are found.
+pub const EXPIRING_CERTS_EXIT_CODE: i32 = 2;
+
+#[derive(Serialize, Debug)]
+pub struct ExpiringCertificate {
+    /// The application owning the trust anchor, or the file the certificate was read from.
+    pub source: String,
+    pub subject: String,
+    pub serial: String,
+    pub not_after: DateTime<Utc>,
+}
+
+pub async fn audit_certificates(
+    config: &Context,
+    within: Duration,
+    issued_certs: Option<&Path>,
+) -> Result<Outcome<Vec<ExpiringCertificate>>, DrogueError> {
+    let deadline = Utc::now() + within;
+    let mut expiring = Vec::new();
+
+    let apps = match ApplicationOperation::new(None, None, None)?
+        .list(config, None)
+        .await?
+    {
+        Outcome::SuccessWithJsonData(apps) => apps,
+        Outcome::SuccessWithMessage(_) => Vec::new(),
+    };
+
+    for app in apps {
+        let name = app.metadata.name.clone();
+        let anchors = match app.section::<ApplicationSpecTrustAnchors>() {
+            Some(Ok(anchors)) => anchors,
+            Some(Err(e)) => {
+                log::warn!("Cannot read trust anchors of application {}: {}", name, e);
+                continue;
+            }
+            None => continue,
+        };
+
+        for anchor in anchors.anchors {
+            expiring.extend(expiring_in_pem(&anchor.certificate, &name, deadline));
+        }
+    }
+
+    if let Some(dir) = issued_certs {
+        for file in walk_dir(dir)? {
+            let content = fs::read(&file)?;
+            let source = file.display().to_string();
+
+            if content.starts_with(b"-----BEGIN") {
+                expiring.extend(expiring_in_pem(&content, &source, deadline));
+            } else if let Ok((_, cert)) = x509_parser::parse_x509_certificate(&content) {
+                expiring.extend(check_expiry(&cert, &source, deadline));
+            } else {
+                log::debug!("{} is not a certificate, skipping.", source);
+            }
+        }
+    }
+
+    expiring.sort_by_key(|c| c.not_after);
+    Ok(Outcome::SuccessWithJsonData(expiring))
+}
+
+fn expiring_in_pem(
+    content: &[u8],
+    source: &str,
+    deadline: DateTime<Utc>,
+) -> Vec<ExpiringCertificate> {
+    let mut expiring = Vec::new();
+
+    for pem in Pem::iter_from_buffer(content) {
+        let pem = match pem {
+            Ok(pem) => pem,
+            Err(e) => {
+                log::warn!("Invalid PEM data in {}: {}", source, e);
+                break;
+            }
+        };
+        if pem.label != "CERTIFICATE" {
+            continue;
+        }
+
+        match pem.parse_x509() {
+            Ok(cert) => expiring.extend(check_expiry(&cert, source, deadline)),
+            Err(e) => log::warn!("Cannot parse certificate from {}: {}", source, e),
+        }
+    }
+
+    expiring
+}
+
+fn check_expiry(
+    cert: &x509_parser::certificate::X509Certificate,
+    source: &str,
+    deadline: DateTime<Utc>,
+) -> Option<ExpiringCertificate> {
+    let not_after = Utc.timestamp(cert.validity().not_after.timestamp(), 0);
+
+    if not_after <= deadline {
+        Some(ExpiringCertificate {
+            source: source.to_string(),
+            subject: cert.subject().to_string(),
+            serial: cert.tbs_certificate.raw_serial_as_string(),
+            not_after,
+        })
+    } else {
+        None
+    }
+}
+
+fn walk_dir(dir: &Path) -> Result<Vec<PathBuf>, DrogueError> {
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        // The file type does not follow symbolic links: linked directories may form a cycle
+        if entry.file_type()?.is_dir() {
+            files.extend(walk_dir(&path)?);
+        } else if path.is_dir() {
+            log::debug!("{} is a linked directory, skipping.", path.display());
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(files)
+}
+
+pub fn expiring_table(certs: &Vec<ExpiringCertificate>) {
+    if certs.is_empty() {
+        println!("No certificates expiring in the given time window.");
+        return;
+    }
+
+    let mut table = Table::new("{:<} | {:<} | {:<} | {:<}");
+    table.add_row(
+        Row::new()
+            .with_cell("SOURCE")
+            .with_cell("SUBJECT")
+            .with_cell("NOT AFTER")
+            .with_cell("EXPIRES IN"),
+    );
+
+    for cert in certs {
+        let remaining = cert.not_after - Utc::now();
+        let expires_in = if remaining < Duration::zero() {
+            "EXPIRED".to_string()
+        } else if remaining > Duration::days(1) {
+            format!("{}d", remaining.num_days())
+        } else {
+            format!("{}h", remaining.num_hours())
+        };
+
+        table.add_row(
+            Row::new()
+                .with_cell(&cert.source)
+                .with_cell(&cert.subject)
+                .with_cell(cert.not_after.to_rfc3339())
+                .with_cell(expires_in),
+        );
+    }
+    print!("{}", table);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_dir_with_link_cycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("nested");
+        fs::create_dir(&nested).unwrap();
+        fs::write(nested.join("cert.pem"), "").unwrap();
+        std::os::unix::fs::symlink(dir.path(), nested.join("loop")).unwrap();
+
+        let files = walk_dir(dir.path()).unwrap();
+        assert_eq!(files, vec![nested.join("cert.pem")]);
+    }
+}
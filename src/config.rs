@@ -1,9 +1,15 @@
-use crate::util::SignAlgo;
+use crate::openid::{self, OidcSettings};
+use crate::util::{self, CertificateProfile, Encrypted, KeyPolicy, Passphrase, SignAlgo};
 
 use anyhow::{anyhow, Context as AnyhowContext, Result};
 use base64::{engine::general_purpose, Engine as _};
 use serde::{Deserialize, Serialize};
-use std::{env, fs::create_dir_all, fs::write, fs::File, path::Path, process::exit};
+use std::io::Write;
+use std::str::FromStr;
+use std::{
+    env, fs, fs::create_dir_all, fs::File, fs::OpenOptions, path::Path, path::PathBuf,
+    process::exit,
+};
 
 use async_trait::async_trait;
 use drogue_client::openid::{Credentials, TokenProvider};
@@ -19,36 +25,271 @@ use oauth2::TokenResponse;
 use tabular::{Row, Table};
 use url::Url;
 
-#[derive(Serialize, Deserialize, Debug)]
+const REDACTED: &str = "<redacted>";
+
+/// The version of the config file layout, increased with each migration.
+const CONFIG_VERSION: u32 = 2;
+
+/// The version of the `drg config export` format.
+const EXPORT_VERSION: u32 = 1;
+
+// Upgrades a config file from the previous version, `MIGRATIONS[n]` migrates version `n + 1`.
+type Migration = fn(&mut serde_yaml::Value) -> Result<()>;
+const MIGRATIONS: [Migration; 1] = [split_contexts];
+
+/// The configuration, with the contexts resolved from their cluster and user.
+///
+/// It is saved as separate lists of clusters, users and contexts referring to them, see `ConfigFile`.
+/// Each context holds its own copy of the cluster settings and credentials: the clusters and users are
+/// only how they are saved, the contexts with the same settings share a cluster and the ones with the
+/// same user share its token.
+#[derive(Debug)]
 pub struct Config {
     pub active_context: Option<String>,
+    /// Encrypt the tokens of the contexts with a passphrase when saving the file.
+    pub encrypt_tokens: bool,
     pub contexts: Vec<Context>,
-    #[serde(skip, default)]
+    /// The credentials of the contexts of other files, they are only saved in the first file.
+    logins: Vec<Login>,
+    // A file after the first one, only the cluster and defaults of its contexts are saved in it.
+    shared: bool,
     changed: bool,
+    passphrase: Option<Passphrase>,
+    loaded: Loaded,
     //todo : when loading, put a ref to the active context for faster access
     // to avoid looping through the contexts each time.
     //#[serde(skip)]
     //pub active_ctx_ref: Option<&'a Context>,
 }
 
+// The configuration as it was loaded, to find out what was changed since.
+#[derive(Debug, Default)]
+struct Loaded {
+    active_context: Option<String>,
+    encrypt_tokens: bool,
+    contexts: Vec<Context>,
+    logins: Vec<Login>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Context {
     pub name: String,
+    /// The name of the cluster, shared by the contexts using the same drogue cloud instance.
+    #[serde(default)]
+    pub cluster: String,
+    /// The name of the user holding the token, shared by the contexts using the same identity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
     pub drogue_cloud_url: Url,
     pub default_app: Option<String>,
     pub default_algo: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cert_profile: Option<CertificateProfile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_policy: Option<KeyPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oidc: Option<OidcSettings>,
+    pub auth_url: Url,
+    pub token_url: Url,
+    pub registry_url: Url,
+    // Contexts shared with a team may be saved without tokens
+    #[serde(default)]
+    pub token_exp_date: DateTime<Utc>,
+    #[serde(default)]
+    pub token: Token,
+    /// The file the context was loaded from, when several config files are used.
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+}
+
+/// A drogue cloud instance, and the endpoints discovered when logging in.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Cluster {
+    pub name: String,
+    pub drogue_cloud_url: Url,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oidc: Option<OidcSettings>,
     pub auth_url: Url,
     pub token_url: Url,
     pub registry_url: Url,
+}
+
+/// The credentials of an identity.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+    pub name: String,
+    #[serde(default)]
     pub token_exp_date: DateTime<Utc>,
+    #[serde(default)]
     pub token: Token,
 }
 
+// A context as saved in the file, referring to its cluster and user.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ContextEntry {
+    name: String,
+    cluster: String,
+    // Contexts shared with a team may be saved without user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    default_app: Option<String>,
+    default_algo: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cert_profile: Option<CertificateProfile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_policy: Option<KeyPolicy>,
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    origin: Option<String>,
+}
+
+// The user of a context of another config file, saved in the first file. The other files only hold
+// the cluster and defaults of their contexts, e.g. when shared with a team.
+#[derive(Serialize, Debug, Clone)]
+struct Login {
+    context: String,
+    user: User,
+}
+
+// A login as saved in the file, referring to its user.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct LoginEntry {
+    context: String,
+    user: String,
+}
+
+/// A context shared with `drg config export`, with its credentials only when asked for.
 #[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportedContext {
+    pub version: u32,
+    pub name: String,
+    pub cluster: Cluster,
+    pub default_app: Option<String>,
+    pub default_algo: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cert_profile: Option<CertificateProfile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_policy: Option<KeyPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<User>,
+}
+
+// The layout of the config file, upgraded from older versions by `migrate`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct ConfigFile {
+    version: u32,
+    active_context: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    encrypt_tokens: bool,
+    #[serde(default)]
+    clusters: Vec<Cluster>,
+    #[serde(default)]
+    users: Vec<User>,
+    #[serde(default)]
+    contexts: Vec<ContextEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    logins: Vec<LoginEntry>,
+}
+
+impl Serialize for Config {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_file().serialize(serializer)
+    }
+}
+
+impl ExportedContext {
+    fn new(context: Context, with_secrets: bool) -> Self {
+        let cluster = context.cluster();
+        let user = match context.token {
+            Token::LoggedOut => None,
+            _ if !with_secrets => None,
+            token => Some(User {
+                name: context.user.unwrap_or_else(|| context.name.clone()),
+                token_exp_date: context.token_exp_date,
+                token,
+            }),
+        };
+
+        ExportedContext {
+            version: EXPORT_VERSION,
+            name: context.name,
+            cluster,
+            default_app: context.default_app,
+            default_algo: context.default_algo,
+            cert_profile: context.cert_profile,
+            key_policy: context.key_policy,
+            user,
+        }
+    }
+
+    /// The export as YAML, or as base64 encoded YAML.
+    pub fn encode(&self, base64: bool) -> Result<String> {
+        let yaml = serde_yaml::to_string(self)?;
+        if base64 {
+            Ok(general_purpose::STANDARD.encode(yaml))
+        } else {
+            Ok(yaml)
+        }
+    }
+
+    /// Read an export, as YAML or as base64 encoded YAML.
+    pub fn decode(content: &str) -> Result<Self> {
+        let exported: ExportedContext = match general_purpose::STANDARD.decode(content.trim()) {
+            Ok(yaml) => serde_yaml::from_slice(&yaml)?,
+            Err(_) => {
+                serde_path_to_error::deserialize(serde_yaml::Deserializer::from_str(content))?
+            }
+        };
+
+        if exported.version > EXPORT_VERSION {
+            return Err(anyhow!(
+                "The export version {} is not supported, this drg reads up to version {}. Please upgrade drg.",
+                exported.version,
+                EXPORT_VERSION
+            ));
+        }
+        Ok(exported)
+    }
+
+    /// The context to add to the configuration, logged out when the export holds no credentials.
+    /// Its cluster and user are set when it is added.
+    pub fn into_context(self, name: String) -> Context {
+        let (token_exp_date, token) = self
+            .user
+            .map(|u| (u.token_exp_date, u.token))
+            .unwrap_or_default();
+
+        Context {
+            name,
+            cluster: String::new(),
+            user: None,
+            drogue_cloud_url: self.cluster.drogue_cloud_url,
+            default_app: self.default_app,
+            default_algo: self.default_algo,
+            cert_profile: self.cert_profile,
+            key_policy: self.key_policy,
+            oidc: self.cluster.oidc,
+            auth_url: self.cluster.auth_url,
+            token_url: self.cluster.token_url,
+            registry_url: self.cluster.registry_url,
+            token_exp_date,
+            token,
+            origin: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(untagged)]
+#[allow(clippy::enum_variant_names)]
 pub enum Token {
     TokenResponse(BasicTokenResponse),
     AccessToken(AccessToken),
+    ClientCredentials(ClientCredentials),
+    /// The token material, encrypted with the passphrase of the configuration file.
+    Encrypted(Encrypted),
+    /// The tokens were revoked with `drg logout`, the context needs a new login.
+    #[default]
+    LoggedOut,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -57,6 +298,67 @@ pub struct AccessToken {
     pub token: String,
 }
 
+/// A service account token, obtained with the OAuth2 client credentials grant.
+/// It cannot be refreshed, a new one is requested with the client credentials instead.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClientCredentials {
+    pub client_id: String,
+    pub client_secret: String,
+    pub token: BasicTokenResponse,
+}
+
+impl Token {
+    pub fn is_encrypted(&self) -> bool {
+        matches!(self, Token::Encrypted(_))
+    }
+
+    fn encrypt(&self, passphrase: &Passphrase) -> Result<Token> {
+        match self {
+            Token::Encrypted(_) | Token::LoggedOut => Ok(self.clone()),
+            _ => Ok(Token::Encrypted(util::encrypt(
+                passphrase,
+                &serde_json::to_vec(self)?,
+            )?)),
+        }
+    }
+
+    fn decrypt(&self, passphrase: &Passphrase) -> Result<Token> {
+        match self {
+            Token::Encrypted(data) => {
+                let token = util::decrypt(passphrase, data)?;
+                serde_json::from_slice(&token).context("Invalid decrypted token")
+            }
+            _ => Ok(self.clone()),
+        }
+    }
+
+    /// A copy of the token with the secrets replaced by a placeholder.
+    pub fn redacted(&self) -> Token {
+        let redact = |token: &BasicTokenResponse| {
+            let mut token = token.clone();
+            token.set_access_token(oauth2::AccessToken::new(REDACTED.to_string()));
+            if token.refresh_token().is_some() {
+                token.set_refresh_token(Some(oauth2::RefreshToken::new(REDACTED.to_string())));
+            }
+            token
+        };
+
+        match self {
+            Token::TokenResponse(token) => Token::TokenResponse(redact(token)),
+            Token::AccessToken(auth) => Token::AccessToken(AccessToken {
+                id: auth.id.clone(),
+                token: REDACTED.to_string(),
+            }),
+            Token::ClientCredentials(credentials) => Token::ClientCredentials(ClientCredentials {
+                client_id: credentials.client_id.clone(),
+                client_secret: REDACTED.to_string(),
+                token: redact(&credentials.token),
+            }),
+            Token::Encrypted(_) | Token::LoggedOut => self.clone(),
+        }
+    }
+}
+
 pub trait RequestBuilderExt {
     fn auth(self, token: &Token) -> Self;
 }
@@ -64,8 +366,12 @@ pub trait RequestBuilderExt {
 impl RequestBuilderExt for reqwest::RequestBuilder {
     fn auth(self, token: &Token) -> Self {
         match token {
-            Token::TokenResponse(token) => self.bearer_auth(&token.access_token().secret()),
+            Token::TokenResponse(token)
+            | Token::ClientCredentials(ClientCredentials { token, .. }) => {
+                self.bearer_auth(token.access_token().secret())
+            }
             Token::AccessToken(auth) => self.basic_auth(&auth.id, Some(&auth.token)),
+            Token::LoggedOut | Token::Encrypted(_) => self,
         }
     }
 }
@@ -73,7 +379,8 @@ impl RequestBuilderExt for reqwest::RequestBuilder {
 impl RequestBuilderExt for tungstenite::http::Request<()> {
     fn auth(mut self, token: &Token) -> Self {
         match token {
-            Token::TokenResponse(token) => {
+            Token::TokenResponse(token)
+            | Token::ClientCredentials(ClientCredentials { token, .. }) => {
                 let bearer_header = format!("Bearer {}", &token.access_token().secret());
                 let mut bearer_header =
                     tungstenite::http::HeaderValue::from_str(&bearer_header).unwrap();
@@ -93,6 +400,7 @@ impl RequestBuilderExt for tungstenite::http::Request<()> {
                     .insert(tungstenite::http::header::AUTHORIZATION, basic_header);
                 self
             }
+            Token::LoggedOut | Token::Encrypted(_) => self,
         }
     }
 }
@@ -101,29 +409,113 @@ impl Config {
     pub fn empty() -> Config {
         Config {
             active_context: None,
+            encrypt_tokens: false,
             contexts: Vec::new(),
+            logins: Vec::new(),
+            shared: false,
             changed: true,
+            passphrase: None,
+            loaded: Loaded::default(),
             //active_ctx_ref: None,
         }
     }
+    /// Load the configuration, from several files when they are separated as in `$PATH`.
+    ///
+    /// The contexts of all the files are merged, the first file defining a context wins.
+    /// The tokens encryption is set in the first file, the active context in the first file setting it.
+    /// The credentials of the contexts of other files are saved in the first file, see `Login`.
     pub fn from(path: Option<&str>) -> Result<Config, DrogueError> {
-        let path = eval_config_path(path);
-        log::info!("Loading configuration file: {}", &path);
+        Config::load(path)?.ok_or_else(|| {
+            DrogueError::ConfigIssue(
+                "Cannot open config file. Did you log in into a drogue-cloud instance?".to_string(),
+            )
+        })
+    }
 
-        let file = File::open(path).map_err(|e| {
-            DrogueError::ConfigIssue(format!(
-                "Cannot open config file. Did you log in into a drogue-cloud instance? {e}"
-            ))
-        })?;
-        let config: Config = serde_yaml::from_reader(file)
-            .map_err(|_| DrogueError::ConfigIssue("Cannot deserialize config file.".to_string()))?;
+    /// Load the configuration as `Config::from`, `None` when none of the files exist.
+    pub fn load(path: Option<&str>) -> Result<Option<Config>, DrogueError> {
+        let paths = eval_config_paths(path);
+        let mut config = Config::empty();
+        let mut found = false;
+
+        for (index, path) in paths.iter().enumerate() {
+            log::info!("Loading configuration file: {}", path);
+            // Older files are migrated in memory, the first one is upgraded when it is saved
+            let layer = match read_config(path) {
+                Ok(Some((layer, _))) => layer,
+                Ok(None) => {
+                    log::debug!("{} does not exist, skipping it", path);
+                    continue;
+                }
+                Err(e) => {
+                    return Err(DrogueError::ConfigIssue(format!(
+                        "Invalid config file {}: {}",
+                        path, e
+                    )))
+                }
+            };
+            found = true;
+
+            if index == 0 {
+                config.encrypt_tokens = layer.encrypt_tokens;
+                config.logins = layer.logins;
+            }
+            if config.active_context.is_none() {
+                config.active_context = layer.active_context;
+            }
+            for mut context in layer.contexts {
+                if config.contains_context(&context.name) {
+                    log::debug!("Context {} of {} is overridden", context.name, path);
+                } else if paths.len() > 1 {
+                    if index > 0 {
+                        config.apply_login(&mut context);
+                    }
+                    context.origin = Some(path.clone());
+                    config.contexts.push(context);
+                } else {
+                    config.contexts.push(context);
+                }
+            }
+        }
+
+        if !found {
+            return Ok(None);
+        }
+        config.changed = false;
+        config.loaded = config.snapshot();
 
         // let active_ref = config.get_active_context()?;
         // config.active_ctx_ref = Some(active_ref);
-        Ok(config)
+        Ok(Some(config))
     }
 
-    pub fn add_context(&mut self, context: Context) -> Result<()> {
+    pub fn add_context(&mut self, mut context: Context) -> Result<()> {
+        if context.cluster.is_empty() {
+            context.cluster = self.cluster_name(&context);
+        }
+        if let Ok(existing) = self.get_context_as_ref(&context.name) {
+            // The context is saved in the file it was loaded from, logging in again renews
+            // the credentials of its user, whichever contexts share it.
+            context.origin = existing.origin.clone();
+            context.user = context.user.or_else(|| existing.user.clone());
+            // Logging in again to the same drogue cloud keeps the defaults of the context
+            if existing.drogue_cloud_url == context.drogue_cloud_url {
+                context.default_app = context.default_app.or_else(|| existing.default_app.clone());
+                context.default_algo = context
+                    .default_algo
+                    .or_else(|| existing.default_algo.clone());
+                context.cert_profile = context
+                    .cert_profile
+                    .or_else(|| existing.cert_profile.clone());
+                context.key_policy = context.key_policy.or_else(|| existing.key_policy.clone());
+                context.oidc = context.oidc.or_else(|| existing.oidc.clone());
+            }
+        }
+        // A new login never gets the credentials of another context
+        if context.user.is_none() {
+            context.user = Some(self.user_name(&context.name));
+        }
+
         let name = &context.name;
         if self.contains_context(name) {
             self.replace_context(context)?;
@@ -145,6 +537,362 @@ impl Config {
         Ok(())
     }
 
+    // The cluster of the contexts using the same drogue cloud with the same settings, or a new one
+    // named after its host.
+    fn cluster_name(&self, context: &Context) -> String {
+        let cluster = context.cluster();
+        if let Some(existing) = self
+            .contexts
+            .iter()
+            .find(|c| same_cluster(&c.cluster(), &cluster))
+        {
+            return existing.cluster.clone();
+        }
+
+        let host = context.drogue_cloud_url.host_str().unwrap_or("cluster");
+        let mut name = host.to_string();
+        let mut index = 1;
+        while self.contexts.iter().any(|c| c.cluster == name) {
+            index += 1;
+            name = format!("{}-{}", host, index);
+        }
+        name
+    }
+
+    // A user named after the context, that no other context uses.
+    fn user_name(&self, context: &str) -> String {
+        let used = |name: &str| {
+            self.contexts
+                .iter()
+                .any(|c| c.user.as_deref() == Some(name))
+                || self.logins.iter().any(|l| l.user.name == name)
+        };
+
+        let mut name = context.to_string();
+        let mut index = 1;
+        while used(&name) {
+            index += 1;
+            name = format!("{}-{}", context, index);
+        }
+        name
+    }
+
+    /// Create or update a context using the cluster and the user of other contexts.
+    pub fn set_context(
+        &mut self,
+        name: &str,
+        cluster: Option<&str>,
+        user: Option<&str>,
+    ) -> Result<Outcome<String>, DrogueError> {
+        let cluster = cluster
+            .map(|cluster| {
+                self.contexts
+                    .iter()
+                    .find(|c| c.cluster == cluster)
+                    .cloned()
+                    .ok_or_else(|| {
+                        DrogueError::InvalidInput(format!("Cluster {} does not exist", cluster))
+                    })
+            })
+            .transpose()?;
+        let user = user
+            .map(|user| {
+                self.contexts
+                    .iter()
+                    .find(|c| c.user.as_deref() == Some(user))
+                    .cloned()
+                    .ok_or_else(|| {
+                        DrogueError::InvalidInput(format!("User {} does not exist", user))
+                    })
+            })
+            .transpose()?;
+
+        let mut context = match (self.get_context_as_ref(name).ok(), &cluster) {
+            (Some(existing), _) => existing.clone(),
+            (None, Some(cluster)) => Context {
+                name: name.to_string(),
+                default_app: None,
+                default_algo: None,
+                cert_profile: None,
+                key_policy: None,
+                user: None,
+                token: Token::LoggedOut,
+                token_exp_date: Utc::now(),
+                origin: None,
+                ..cluster.clone()
+            },
+            (None, None) => {
+                return Err(DrogueError::InvalidInput(format!(
+                    "Context {} does not exist, a cluster is needed to create it",
+                    name
+                )))
+            }
+        };
+
+        if let Some(cluster) = cluster {
+            context.cluster = cluster.cluster;
+            context.drogue_cloud_url = cluster.drogue_cloud_url;
+            context.oidc = cluster.oidc;
+            context.auth_url = cluster.auth_url;
+            context.token_url = cluster.token_url;
+            context.registry_url = cluster.registry_url;
+        }
+        if let Some(user) = user {
+            context.user = user.user;
+            context.token = user.token;
+            context.token_exp_date = user.token_exp_date;
+        }
+
+        let message = format!(
+            "Context {} uses cluster {} and user {}",
+            name,
+            context.cluster,
+            context.user.as_deref().unwrap_or("<none>")
+        );
+        self.add_context(context)
+            .map_err(|e| DrogueError::ConfigIssue(e.to_string()))?;
+        Ok(SuccessWithMessage(message))
+    }
+
+    /// Export a context, with its token decrypted when the credentials are included.
+    pub fn export_context(
+        &mut self,
+        name: &Option<String>,
+        with_secrets: bool,
+    ) -> Result<ExportedContext> {
+        let context = if with_secrets {
+            self.unlock_context(name)?.clone()
+        } else {
+            self.get_context(name)?.clone()
+        };
+        Ok(ExportedContext::new(context, with_secrets))
+    }
+
+    fn from_file(file: ConfigFile) -> Result<Config> {
+        let mut config = Config::empty();
+        config.changed = false;
+        config.active_context = file.active_context;
+        config.encrypt_tokens = file.encrypt_tokens;
+
+        for entry in file.contexts {
+            let cluster = file
+                .clusters
+                .iter()
+                .find(|c| c.name == entry.cluster)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Context {} refers to the unknown cluster {}",
+                        entry.name,
+                        entry.cluster
+                    )
+                })?;
+            let user = entry
+                .user
+                .as_ref()
+                .map(|name| {
+                    file.users.iter().find(|u| &u.name == name).ok_or_else(|| {
+                        anyhow!("Context {} refers to the unknown user {}", entry.name, name)
+                    })
+                })
+                .transpose()?;
+
+            let context = Context {
+                name: entry.name,
+                cluster: entry.cluster,
+                user: entry.user,
+                drogue_cloud_url: cluster.drogue_cloud_url.clone(),
+                default_app: entry.default_app,
+                default_algo: entry.default_algo,
+                cert_profile: entry.cert_profile,
+                key_policy: entry.key_policy,
+                oidc: cluster.oidc.clone(),
+                auth_url: cluster.auth_url.clone(),
+                token_url: cluster.token_url.clone(),
+                registry_url: cluster.registry_url.clone(),
+                token_exp_date: user.map(|u| u.token_exp_date).unwrap_or_default(),
+                token: user.map(|u| u.token.clone()).unwrap_or_default(),
+                origin: None,
+            };
+            config.contexts.push(context);
+        }
+
+        for entry in file.logins {
+            let user = file
+                .users
+                .iter()
+                .find(|u| u.name == entry.user)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "The login of context {} refers to the unknown user {}",
+                        entry.context,
+                        entry.user
+                    )
+                })?;
+            config.logins.push(Login {
+                context: entry.context,
+                user: user.clone(),
+            });
+        }
+
+        Ok(config)
+    }
+
+    fn to_file(&self) -> ConfigFile {
+        let mut file = ConfigFile {
+            version: CONFIG_VERSION,
+            active_context: self.active_context.clone(),
+            encrypt_tokens: self.encrypt_tokens,
+            ..Default::default()
+        };
+
+        for context in &self.contexts {
+            // The contexts of a cluster with different settings, e.g. their OpenID client, are
+            // saved with a cluster of their own
+            let mut cluster = context.cluster();
+            let mut index = 1;
+            loop {
+                match file.clusters.iter().find(|c| c.name == cluster.name) {
+                    Some(existing) if same_cluster(existing, &cluster) => break,
+                    Some(_) => {
+                        index += 1;
+                        cluster.name = format!("{}-{}", context.cluster, index);
+                    }
+                    None => {
+                        file.clusters.push(cluster.clone());
+                        break;
+                    }
+                }
+            }
+
+            if let Some(name) = &context.user {
+                add_user(
+                    &mut file.users,
+                    User {
+                        name: name.clone(),
+                        token_exp_date: context.token_exp_date,
+                        token: context.token.clone(),
+                    },
+                );
+            }
+
+            file.contexts.push(ContextEntry {
+                name: context.name.clone(),
+                cluster: cluster.name,
+                user: context.user.clone(),
+                default_app: context.default_app.clone(),
+                default_algo: context.default_algo.clone(),
+                cert_profile: context.cert_profile.clone(),
+                key_policy: context.key_policy.clone(),
+                origin: context.origin.clone(),
+            });
+        }
+
+        for login in &self.logins {
+            add_user(&mut file.users, login.user.clone());
+            file.logins.push(LoginEntry {
+                context: login.context.clone(),
+                user: login.user.name.clone(),
+            });
+        }
+
+        file
+    }
+
+    // A context of another file uses the credentials saved for it in the first file.
+    fn apply_login(&self, context: &mut Context) {
+        if let Some(login) = self.logins.iter().find(|l| l.context == context.name) {
+            context.user = Some(login.user.name.clone());
+            context.token = login.user.token.clone();
+            context.token_exp_date = login.user.token_exp_date;
+        }
+    }
+
+    // The credentials of the contexts of other files, with the ones changed since they were loaded.
+    fn changed_logins(&self, primary: &str) -> Vec<Login> {
+        // The logins of deleted contexts are removed, the ones of files not loaded are kept
+        let mut logins: Vec<Login> = self
+            .logins
+            .iter()
+            .filter(|l| {
+                self.contains_context(&l.context)
+                    || !self.loaded.contexts.iter().any(|c| c.name == l.context)
+            })
+            .cloned()
+            .collect();
+        // Switching the encryption changes the tokens of the logins as well
+        let reencrypted = self.encrypt_tokens != self.loaded.encrypt_tokens;
+
+        for context in self
+            .contexts
+            .iter()
+            .filter(|c| c.origin.as_deref().unwrap_or(primary) != primary)
+        {
+            let unchanged = self.loaded.contexts.iter().any(|loaded| {
+                loaded.name == context.name
+                    && loaded.user == context.user
+                    && loaded.token_exp_date == context.token_exp_date
+                    && same_token(&loaded.token, &context.token)
+            });
+            let has_login = logins.iter().any(|l| l.context == context.name);
+            if unchanged && !(reencrypted && has_login) {
+                continue;
+            }
+
+            logins.retain(|l| l.context != context.name);
+            if let Some(name) = &context.user {
+                logins.push(Login {
+                    context: context.name.clone(),
+                    user: User {
+                        name: name.clone(),
+                        token_exp_date: context.token_exp_date,
+                        token: context.token.clone(),
+                    },
+                });
+            }
+        }
+        logins
+    }
+
+    // The token saved for a context, in the context or in its login.
+    fn saved_credentials(&self, name: &str) -> Option<(&Token, DateTime<Utc>)> {
+        match self.get_context_as_ref(name) {
+            Ok(context) => Some((&context.token, context.token_exp_date)),
+            Err(_) => self
+                .logins
+                .iter()
+                .find(|l| l.context == name)
+                .map(|l| (&l.user.token, l.user.token_exp_date)),
+        }
+    }
+
+    // The contexts sharing a user get the credentials renewed through one of them.
+    fn share_credentials(&mut self) {
+        let renewed: Vec<Context> = self
+            .changed_contexts()
+            .into_iter()
+            .filter(|context| {
+                context.user.is_some()
+                    && !self.loaded.contexts.iter().any(|loaded| {
+                        loaded.name == context.name
+                            && loaded.user == context.user
+                            && loaded.token_exp_date == context.token_exp_date
+                            && same_token(&loaded.token, &context.token)
+                    })
+            })
+            .collect();
+
+        for renewed in renewed {
+            for context in self
+                .contexts
+                .iter_mut()
+                .filter(|c| c.user == renewed.user && c.name != renewed.name)
+            {
+                context.token = renewed.token.clone();
+                context.token_exp_date = renewed.token_exp_date;
+            }
+        }
+    }
+
     pub fn get_context(&self, name: &Option<String>) -> Result<&Context> {
         match name {
             Some(n) => self.get_context_as_ref(n),
@@ -158,6 +906,190 @@ impl Config {
             None => self.get_active_context_mut(),
         }
     }
+
+    /// Get a context with its tokens decrypted, the passphrase is asked for if they are encrypted.
+    pub fn unlock_context(&mut self, name: &Option<String>) -> Result<&mut Context> {
+        if self.get_context(name)?.token.is_encrypted() {
+            let passphrase = self.passphrase()?;
+            let context = self.get_context_mut(name)?;
+            context.token = context.token.decrypt(&passphrase).with_context(|| {
+                format!("Cannot decrypt the tokens of context {}", context.name)
+            })?;
+
+            // Decrypting the token is not a change to save
+            let context = context.clone();
+            if let Some(loaded) = self
+                .loaded
+                .contexts
+                .iter_mut()
+                .find(|c| c.name == context.name)
+            {
+                loaded.token = context.token;
+            }
+        }
+        self.get_context_mut(name)
+    }
+
+    /// Get a context with a valid token, refreshing it and saving it if it expires soon.
+    ///
+    /// A refresh token can only be used once, so the config file of the context is locked from
+    /// the time it is read again until the new token is saved: concurrent drg processes wait for
+    /// the lock, then find the refreshed token in the file.
+    pub async fn refresh_context(
+        &mut self,
+        path: Option<&str>,
+        name: &Option<String>,
+    ) -> Result<&mut Context> {
+        if !openid::token_expires_soon(self.unlock_context(name)?) {
+            return self.get_context_mut(name);
+        }
+
+        // The credentials are saved in the first file
+        let paths = eval_config_paths(path);
+        let file = &paths[0];
+        let context_name = Some(self.get_context(name)?.name.clone());
+        let _lock = lock_config(file)?;
+
+        // Another drg process may have refreshed the token since the file was loaded
+        if let Some((current, _)) = read_config(file)? {
+            let name = context_name.as_deref().unwrap_or_default();
+            if let Some((latest, exp_date)) = current.saved_credentials(name) {
+                if exp_date > self.get_context(&context_name)?.token_exp_date {
+                    let token = if latest.is_encrypted() {
+                        latest.decrypt(&self.passphrase()?)?
+                    } else {
+                        latest.clone()
+                    };
+                    for context in self
+                        .contexts
+                        .iter_mut()
+                        .chain(self.loaded.contexts.iter_mut())
+                        .filter(|c| Some(&c.name) == context_name.as_ref())
+                    {
+                        context.token = token.clone();
+                        context.token_exp_date = exp_date;
+                    }
+                }
+            }
+        }
+
+        if openid::verify_token_validity(self.get_context_mut(&context_name)?).await? {
+            self.changed = true;
+            self.write_locked(path, Some(file))?;
+        }
+        self.get_context_mut(&context_name)
+    }
+
+    pub fn unlock_all(&mut self) -> Result<()> {
+        let names: Vec<String> = self
+            .contexts
+            .iter()
+            .filter(|c| c.token.is_encrypted())
+            .map(|c| c.name.clone())
+            .collect();
+
+        for name in names {
+            self.unlock_context(&Some(name))?;
+        }
+        Ok(())
+    }
+
+    fn passphrase(&mut self) -> Result<Passphrase> {
+        if let Some(passphrase) = &self.passphrase {
+            return Ok(passphrase.clone());
+        }
+
+        let passphrase = Passphrase::read("Passphrase of the drg configuration: ", false)?;
+        // Check it against the tokens already encrypted, so that all use the same passphrase
+        if let Some(context) = self.contexts.iter().find(|c| c.token.is_encrypted()) {
+            context
+                .token
+                .decrypt(&passphrase)
+                .context("Wrong passphrase of the drg configuration")?;
+        }
+        self.passphrase = Some(passphrase.clone());
+        Ok(passphrase)
+    }
+
+    pub fn encrypt_tokens(&mut self) -> Result<Outcome<String>, DrogueError> {
+        if self.contexts.iter().any(|c| c.token.is_encrypted()) {
+            // The tokens already encrypted are kept with the same passphrase
+            self.unlock_all()
+                .map_err(|e| DrogueError::ConfigIssue(format!("{:#}", e)))?;
+        } else if self.passphrase.is_none() {
+            let passphrase = Passphrase::read("New passphrase of the drg configuration: ", true)
+                .map_err(|e| DrogueError::InvalidInput(e.to_string()))?;
+            self.passphrase = Some(passphrase);
+        }
+
+        self.encrypt_tokens = true;
+        self.changed = true;
+        Ok(SuccessWithMessage(
+            "The tokens are encrypted in the configuration file".to_string(),
+        ))
+    }
+
+    pub fn decrypt_tokens(&mut self) -> Result<Outcome<String>, DrogueError> {
+        self.unlock_all()
+            .map_err(|e| DrogueError::ConfigIssue(format!("{:#}", e)))?;
+
+        self.encrypt_tokens = false;
+        self.changed = true;
+        Ok(SuccessWithMessage(
+            "The tokens are no longer encrypted in the configuration file".to_string(),
+        ))
+    }
+
+    /// A copy of the configuration with the secrets of the tokens replaced by a placeholder.
+    pub fn redacted(&self) -> Config {
+        Config {
+            active_context: self.active_context.clone(),
+            encrypt_tokens: self.encrypt_tokens,
+            contexts: self.contexts.iter().map(Context::redacted).collect(),
+            // The contexts of other files show their credentials
+            logins: Vec::new(),
+            shared: false,
+            changed: false,
+            passphrase: None,
+            loaded: Loaded::default(),
+        }
+    }
+
+    // A copy of the configuration as saved in the file, with the tokens encrypted if enabled.
+    fn saved_copy(&mut self) -> Result<Config> {
+        let mut contexts = self.contexts.clone();
+        for context in contexts.iter_mut() {
+            context.origin = None;
+        }
+
+        let mut logins = self.logins.clone();
+
+        let plaintext = |token: &Token| !matches!(token, Token::Encrypted(_) | Token::LoggedOut);
+        if self.encrypt_tokens
+            && (contexts.iter().any(|c| plaintext(&c.token))
+                || logins.iter().any(|l| plaintext(&l.user.token)))
+        {
+            let passphrase = self.passphrase()?;
+            for context in contexts.iter_mut() {
+                context.token = context.token.encrypt(&passphrase)?;
+            }
+            for login in logins.iter_mut() {
+                login.user.token = login.user.token.encrypt(&passphrase)?;
+            }
+        }
+
+        Ok(Config {
+            active_context: self.active_context.clone(),
+            encrypt_tokens: self.encrypt_tokens,
+            contexts,
+            logins,
+            shared: self.shared,
+            changed: false,
+            passphrase: None,
+            loaded: Loaded::default(),
+        })
+    }
+
     fn get_active_context(&self) -> Result<&Context> {
         if let Some(default) = &self.active_context.clone() {
             self.get_context_as_ref(default)
@@ -194,7 +1126,7 @@ impl Config {
         }
         Err(anyhow!("Context \"{}\" not found in config file.", name))
     }
-    fn contains_context(&self, name: &str) -> bool {
+    pub fn contains_context(&self, name: &str) -> bool {
         for config in &self.contexts {
             if config.name == name {
                 return true;
@@ -203,7 +1135,9 @@ impl Config {
         false
     }
     pub fn list_contexts(&self) -> Result<Outcome<Vec<Context>>, DrogueError> {
-        Ok(SuccessWithJsonData(self.contexts.clone()))
+        Ok(SuccessWithJsonData(
+            self.contexts.iter().map(Context::redacted).collect(),
+        ))
     }
 
     pub fn set_active_context(&mut self, name: String) -> Result<Outcome<String>, DrogueError> {
@@ -222,37 +1156,316 @@ impl Config {
         }
     }
 
-    pub fn write(&self, path: Option<&str>) -> Result<()> {
+    /// Save the changes to the config files.
+    ///
+    /// A context is saved in the file it was loaded from, unless that file is read-only: it is then
+    /// saved in the first file, overriding the original one. New contexts, the active context and
+    /// the tokens encryption are saved in the first file.
+    ///
+    /// Other drg processes may have saved the files since they were loaded, e.g. after refreshing
+    /// a token, so the changes are merged into the current files, under a lock.
+    pub fn write(&mut self, path: Option<&str>) -> Result<()> {
+        self.write_locked(path, None)
+    }
+
+    // Save the changes, the file `locked` is already locked by the caller.
+    fn write_locked(&mut self, path: Option<&str>, locked: Option<&str>) -> Result<()> {
         if self.changed {
-            let path = eval_config_path(path);
-            if let Some(parent) = Path::new(&path).parent() {
-                create_dir_all(parent)
-                    .context("Failed to create parent directory of configuration")?;
+            self.share_credentials();
+            let paths = eval_config_paths(path);
+            let primary = &paths[0];
+
+            let mut layers = Vec::new();
+            let mut moved = Vec::new();
+            for path in &paths[1..] {
+                let mut layer = self.layer(path, primary);
+                if layer.has_changes() {
+                    if let Err(e) = layer.save(path, false, locked == Some(path)) {
+                        if !is_permission_denied(&e)
+                            && e.downcast_ref::<OlderSharedFile>().is_none()
+                        {
+                            return Err(e);
+                        }
+                        log::warn!(
+                            "Cannot write {} ({}), its changed contexts are saved in {}",
+                            path,
+                            e,
+                            primary
+                        );
+                        for loaded in &layer.loaded.contexts {
+                            if !layer.contains_context(&loaded.name) {
+                                log::warn!(
+                                    "Context {} cannot be deleted from {}",
+                                    loaded.name,
+                                    path
+                                );
+                            }
+                        }
+                        moved.extend(layer.changed_contexts());
+                        layer.contexts = layer.loaded.contexts.clone();
+                    }
+                }
+                self.passphrase = self.passphrase.take().or(layer.passphrase);
+                layers.push((path, layer.contexts));
+            }
+
+            let mut layer = self.layer(primary, primary);
+            for mut context in moved {
+                context.origin = None;
+                layer.logins.retain(|l| l.context != context.name);
+                layer.contexts.retain(|c| c.name != context.name);
+                layer.contexts.push(context);
+            }
+            if layer.has_changes() {
+                layer.save(primary, true, locked == Some(primary))?;
+            }
+            self.active_context = layer.active_context;
+            self.encrypt_tokens = layer.encrypt_tokens;
+            self.passphrase = self.passphrase.take().or(layer.passphrase);
+            self.logins = layer.logins;
+            layers.insert(0, (primary, layer.contexts));
+
+            // Back to the merged view of the files
+            let layered = layers.len() > 1;
+            self.contexts.clear();
+            for (path, contexts) in layers {
+                for mut context in contexts {
+                    if !self.contains_context(&context.name) {
+                        if path != primary {
+                            self.apply_login(&mut context);
+                        }
+                        context.origin = Some(path.clone()).filter(|_| layered);
+                        self.contexts.push(context);
+                    }
+                }
             }
 
-            log::info!("Saving config file: {}", &path);
-            write(&path, serde_yaml::to_string(&self)?)
-                .context(format!("Unable to write config file :{}", path))?;
+            self.loaded = self.snapshot();
+            self.changed = false;
         }
         Ok(())
     }
 
-    pub fn delete_context(&mut self, name: &str) -> Result<Outcome<String>, DrogueError> {
-        if self.contains_context(name) {
-            self.contexts.retain(|c| c.name != name);
+    // The part of the configuration saved in a file.
+    fn layer(&self, path: &str, primary: &str) -> Config {
+        let in_file = |c: &&Context| c.origin.as_deref().unwrap_or(primary) == path;
+        let is_primary = path == primary;
 
-            if self.active_context == Some(name.to_string()) {
-                if !self.contexts.is_empty() {
-                    self.active_context = Some(self.contexts[0].name.clone());
-                } else {
-                    self.active_context = None;
-                }
-            }
-            self.changed = true;
-            Ok(Outcome::SuccessWithMessage(format!(
-                "Context {name} deleted"
-            )))
-        } else {
+        // The tokens encryption of the other files is read when saving them
+        Config {
+            active_context: self.active_context.clone().filter(|_| is_primary),
+            encrypt_tokens: self.encrypt_tokens && is_primary,
+            contexts: self.contexts.iter().filter(in_file).cloned().collect(),
+            logins: if is_primary {
+                self.changed_logins(primary)
+            } else {
+                Vec::new()
+            },
+            shared: !is_primary,
+            changed: false,
+            passphrase: self.passphrase.clone(),
+            loaded: Loaded {
+                active_context: self.loaded.active_context.clone().filter(|_| is_primary),
+                encrypt_tokens: self.loaded.encrypt_tokens && is_primary,
+                contexts: self
+                    .loaded
+                    .contexts
+                    .iter()
+                    .filter(in_file)
+                    .cloned()
+                    .collect(),
+                logins: self
+                    .loaded
+                    .logins
+                    .iter()
+                    .filter(|_| is_primary)
+                    .cloned()
+                    .collect(),
+            },
+        }
+    }
+
+    fn has_changes(&self) -> bool {
+        self.active_context != self.loaded.active_context
+            || self.encrypt_tokens != self.loaded.encrypt_tokens
+            || self.contexts.len() != self.loaded.contexts.len()
+            || !self.changed_contexts().is_empty()
+            || serde_json::to_value(&self.logins).ok()
+                != serde_json::to_value(&self.loaded.logins).ok()
+    }
+
+    // The contexts added or changed since the config was loaded. The credentials of the contexts
+    // of a shared file are not saved in it.
+    fn changed_contexts(&self) -> Vec<Context> {
+        self.contexts
+            .iter()
+            .filter(
+                |context| match self.loaded.contexts.iter().find(|c| c.name == context.name) {
+                    Some(loaded) if self.shared => !same_settings(loaded, context),
+                    Some(loaded) => !same_context(loaded, context),
+                    None => true,
+                },
+            )
+            .cloned()
+            .collect()
+    }
+
+    // Merge the changes into the file and replace it.
+    fn save(&mut self, path: &str, primary: bool, locked: bool) -> Result<()> {
+        if let Some(parent) = Path::new(path).parent() {
+            create_dir_all(parent).context("Failed to create parent directory of configuration")?;
+        }
+
+        let _lock = if locked {
+            None
+        } else {
+            Some(lock_config(path)?)
+        };
+        let mut file_encryption = false;
+        match read_config(path) {
+            Ok(Some((_, version))) if version < CONFIG_VERSION && !primary => {
+                return Err(OlderSharedFile(version).into())
+            }
+            Ok(Some((current, version))) => {
+                if version < CONFIG_VERSION {
+                    backup_config(path, version)?;
+                }
+                file_encryption = current.encrypt_tokens;
+                let merged = self.merge(current);
+                self.active_context = merged.active_context;
+                if primary {
+                    self.encrypt_tokens = merged.encrypt_tokens;
+                }
+                self.contexts = merged.contexts;
+                self.logins = merged.logins;
+            }
+            Ok(None) => {}
+            // Saving would lose the settings of a newer drg, or the content of an invalid file
+            Err(e) => return Err(e.context(format!("Cannot save the config file {}", path))),
+        }
+
+        // The tokens encryption is only set in the first file, the other files keep their own
+        if !primary {
+            self.encrypt_tokens = file_encryption;
+        }
+        let saved = self.saved_copy()?;
+
+        log::info!("Saving config file: {}", path);
+        // The other files may be shared, e.g. readable by a team
+        write_atomic(path, &serde_yaml::to_string(&saved)?, !primary)
+            .context(format!("Unable to write config file :{}", path))
+    }
+
+    fn snapshot(&self) -> Loaded {
+        Loaded {
+            active_context: self.active_context.clone(),
+            encrypt_tokens: self.encrypt_tokens,
+            contexts: self.contexts.clone(),
+            logins: self.logins.clone(),
+        }
+    }
+
+    // Apply the changes made since the config was loaded to the current content of the file.
+    // When a context token was refreshed by another process as well, the newest one is kept.
+    fn merge(&self, mut current: Config) -> Config {
+        // Switching the encryption changes all the tokens
+        let reencrypted = self.encrypt_tokens != self.loaded.encrypt_tokens;
+
+        for loaded in &self.loaded.contexts {
+            if !self.contains_context(&loaded.name) {
+                current.contexts.retain(|c| c.name != loaded.name);
+            }
+        }
+
+        let changed = if reencrypted {
+            self.contexts.clone()
+        } else {
+            self.changed_contexts()
+        };
+        for mut context in changed {
+            match current.contexts.iter_mut().find(|c| c.name == context.name) {
+                // The credentials of a shared file are kept as they are
+                Some(latest) if self.shared => {
+                    context.user = latest.user.clone();
+                    context.token = latest.token.clone();
+                    context.token_exp_date = latest.token_exp_date;
+                    *latest = context;
+                }
+                Some(latest) => {
+                    if latest.token_exp_date > context.token_exp_date
+                        && !matches!(context.token, Token::LoggedOut)
+                    {
+                        context.token = latest.token.clone();
+                        context.token_exp_date = latest.token_exp_date;
+                    }
+                    *latest = context;
+                }
+                None if self.shared => current.contexts.push(Context {
+                    user: None,
+                    token: Token::LoggedOut,
+                    token_exp_date: DateTime::default(),
+                    ..context
+                }),
+                None => current.contexts.push(context),
+            }
+        }
+
+        for loaded in &self.loaded.logins {
+            if !self.logins.iter().any(|l| l.context == loaded.context) {
+                current.logins.retain(|l| l.context != loaded.context);
+            }
+        }
+        let changed = self.logins.iter().filter(|login| {
+            reencrypted
+                || !self.loaded.logins.iter().any(|loaded| {
+                    serde_json::to_value(loaded).ok() == serde_json::to_value(login).ok()
+                })
+        });
+        for login in changed {
+            let mut login = login.clone();
+            match current
+                .logins
+                .iter_mut()
+                .find(|l| l.context == login.context)
+            {
+                Some(latest) => {
+                    if latest.user.token_exp_date > login.user.token_exp_date
+                        && !matches!(login.user.token, Token::LoggedOut)
+                    {
+                        login.user.token = latest.user.token.clone();
+                        login.user.token_exp_date = latest.user.token_exp_date;
+                    }
+                    *latest = login;
+                }
+                None => current.logins.push(login),
+            }
+        }
+
+        if self.active_context != self.loaded.active_context {
+            current.active_context = self.active_context.clone();
+        }
+        if reencrypted {
+            current.encrypt_tokens = self.encrypt_tokens;
+        }
+        current
+    }
+
+    pub fn delete_context(&mut self, name: &str) -> Result<Outcome<String>, DrogueError> {
+        if self.contains_context(name) {
+            self.contexts.retain(|c| c.name != name);
+
+            if self.active_context == Some(name.to_string()) {
+                if !self.contexts.is_empty() {
+                    self.active_context = Some(self.contexts[0].name.clone());
+                } else {
+                    self.active_context = None;
+                }
+            }
+            self.changed = true;
+            Ok(Outcome::SuccessWithMessage(format!(
+                "Context {name} deleted"
+            )))
+        } else {
             Err(DrogueError::InvalidInput(format!(
                 "Context {} does not exist in config file.",
                 name
@@ -322,15 +1535,51 @@ impl Context {
         let dummy_url = Url::parse("https://example.net").unwrap();
         Context {
             name,
+            cluster: String::new(),
+            user: None,
             drogue_cloud_url: api,
             token: Token::AccessToken(auth),
 
             default_app: None,
             default_algo: None,
+            cert_profile: None,
+            key_policy: None,
+            oidc: None,
             auth_url: dummy_url.clone(),
             token_url: dummy_url.clone(),
             registry_url: dummy_url,
             token_exp_date: chrono::DateTime::<Utc>::MAX_UTC,
+            origin: None,
+        }
+    }
+
+    // The cluster settings of the context.
+    fn cluster(&self) -> Cluster {
+        Cluster {
+            name: self.cluster.clone(),
+            drogue_cloud_url: self.drogue_cloud_url.clone(),
+            oidc: self.oidc.clone(),
+            auth_url: self.auth_url.clone(),
+            token_url: self.token_url.clone(),
+            registry_url: self.registry_url.clone(),
+        }
+    }
+
+    /// Names the drogue cloud instance in local data paths, which unlike the context name survives a rename.
+    pub fn instance_id(&self) -> String {
+        let host = self.drogue_cloud_url.host_str().unwrap_or("cluster");
+        match self.drogue_cloud_url.port() {
+            Some(port) => format!("{}_{}", host, port),
+            None => host.to_string(),
+        }
+        .replace(':', "_")
+    }
+
+    /// A copy of the context with the secrets of the token replaced by a placeholder.
+    pub fn redacted(&self) -> Context {
+        Context {
+            token: self.token.redacted(),
+            ..self.clone()
         }
     }
 
@@ -346,13 +1595,67 @@ impl Context {
         ))
     }
 
-    pub fn set_default_algo(&mut self, algo: SignAlgo) -> Outcome<String> {
+    pub fn set_default_algo(&mut self, algo: SignAlgo) -> Result<Outcome<String>, DrogueError> {
+        self.key_policy()
+            .check(algo, None)
+            .map_err(|e| DrogueError::InvalidInput(e.to_string()))?;
+
         self.default_algo = Some(algo.as_ref().to_string());
-        SuccessWithMessage(format!(
+        Ok(SuccessWithMessage(format!(
             "{} set as default certificate algorithm for context {}",
             algo.as_ref(),
             self.name
-        ))
+        )))
+    }
+
+    pub fn set_key_policy(&mut self, policy: KeyPolicy) -> Outcome<String> {
+        if policy == KeyPolicy::default() {
+            self.key_policy = None;
+            SuccessWithMessage(format!("Key policy cleared for context {}", self.name))
+        } else {
+            if let Some(algo) = self.default_algo.as_deref() {
+                if let Err(e) = SignAlgo::from_str(algo)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|algo| policy.check(algo, None))
+                {
+                    log::warn!("The default algorithm of context {}: {}", self.name, e);
+                }
+            }
+            self.key_policy = Some(policy);
+            SuccessWithMessage(format!("Key policy set for context {}", self.name))
+        }
+    }
+
+    pub fn key_policy(&self) -> KeyPolicy {
+        self.key_policy.clone().unwrap_or_default()
+    }
+
+    pub fn oidc(&self) -> OidcSettings {
+        self.oidc.clone().unwrap_or_default()
+    }
+
+    pub fn set_cert_profile(&mut self, profile: CertificateProfile) -> Outcome<String> {
+        if profile == CertificateProfile::default() {
+            self.cert_profile = None;
+            SuccessWithMessage(format!(
+                "Certificate defaults cleared for context {}",
+                self.name
+            ))
+        } else {
+            self.cert_profile = Some(profile);
+            SuccessWithMessage(format!(
+                "Certificate defaults set for context {}",
+                self.name
+            ))
+        }
+    }
+
+    /// The certificate profile of this context, overridden by the given one.
+    pub fn cert_profile(&self, overrides: CertificateProfile) -> CertificateProfile {
+        self.cert_profile
+            .clone()
+            .unwrap_or_default()
+            .merge(overrides)
     }
 
     pub fn fill_urls(&mut self, auth: Url, registry: Url, token: Url) {
@@ -370,10 +1673,354 @@ impl TokenProvider for Token {
                 basic.id.clone(),
                 Some(basic.token.clone()),
             ))),
-            Token::TokenResponse(token) => Ok(Some(Credentials::Bearer(
-                token.access_token().secret().clone(),
-            ))),
+            Token::LoggedOut | Token::Encrypted(_) => Ok(None),
+            Token::TokenResponse(token)
+            | Token::ClientCredentials(ClientCredentials { token, .. }) => Ok(Some(
+                Credentials::Bearer(token.access_token().secret().clone()),
+            )),
+        }
+    }
+}
+
+fn same_context(a: &Context, b: &Context) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+// The same drogue cloud with the same settings, whatever the names.
+fn same_cluster(a: &Cluster, b: &Cluster) -> bool {
+    a.drogue_cloud_url == b.drogue_cloud_url
+        && a.oidc.clone().unwrap_or_default() == b.oidc.clone().unwrap_or_default()
+        && a.auth_url == b.auth_url
+        && a.token_url == b.token_url
+        && a.registry_url == b.registry_url
+}
+
+// The same cluster and defaults, whatever the credentials.
+fn same_settings(a: &Context, b: &Context) -> bool {
+    let settings = |context: &Context| Context {
+        user: None,
+        token: Token::LoggedOut,
+        token_exp_date: DateTime::default(),
+        ..context.clone()
+    };
+    same_context(&settings(a), &settings(b))
+}
+
+// Add a user to the saved ones. The contexts sharing a user may have refreshed its token separately.
+fn add_user(users: &mut Vec<User>, user: User) {
+    match users.iter_mut().find(|u| u.name == user.name) {
+        Some(existing) if existing.token_exp_date > user.token_exp_date => {}
+        Some(existing) => *existing = user,
+        None => users.push(user),
+    }
+}
+
+fn same_token(a: &Token, b: &Token) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+fn is_permission_denied(error: &anyhow::Error) -> bool {
+    error.chain().any(|e| {
+        matches!(e.downcast_ref::<std::io::Error>(), Some(e) if e.kind() == std::io::ErrorKind::PermissionDenied)
+    })
+}
+
+// The config of a file, with the version it was written with.
+fn read_config(path: &str) -> Result<Option<(Config, u32)>> {
+    match fs::read_to_string(path) {
+        Ok(content) => parse_config(&content).map(Some),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// The errors give the path of the invalid field and its line, in the layout of the file version.
+fn parse_config(content: &str) -> Result<(Config, u32)> {
+    let mut value: serde_yaml::Value = serde_yaml::from_str(content)?;
+    let version = if value.is_mapping() {
+        if file_version(&value)? == 1 {
+            serde_path_to_error::deserialize::<_, ConfigV1>(serde_yaml::Deserializer::from_str(
+                content,
+            ))?;
+        }
+        migrate(&mut value)?
+    } else {
+        CONFIG_VERSION
+    };
+
+    let file: ConfigFile = if version == CONFIG_VERSION {
+        serde_path_to_error::deserialize(serde_yaml::Deserializer::from_str(content))?
+    } else {
+        serde_path_to_error::deserialize(value)?
+    };
+    Ok((Config::from_file(file)?, version))
+}
+
+/// A config file written by a newer drg.
+#[derive(Debug)]
+struct UnsupportedVersion(u64);
+
+impl fmt::Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The config file version {} is not supported, this drg reads up to version {}. Please upgrade drg.",
+            self.0, CONFIG_VERSION
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedVersion {}
+
+// The version a config file was written with. Files written before the version was added are version 1.
+fn file_version(file: &serde_yaml::Value) -> Result<u32> {
+    match file.get("version") {
+        None => Ok(1),
+        Some(version) => match version.as_u64() {
+            Some(v) if v > CONFIG_VERSION as u64 => Err(UnsupportedVersion(v).into()),
+            Some(v) if v > 0 => Ok(v as u32),
+            _ => Err(anyhow!(
+                "version: invalid config file version {:?}",
+                version
+            )),
+        },
+    }
+}
+
+// Upgrade the config file content to the current version, returning the version it was written with.
+fn migrate(file: &mut serde_yaml::Value) -> Result<u32> {
+    let version = file_version(file)?;
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        migration(file).map_err(|e| {
+            anyhow!(
+                "Cannot migrate the config file from version {} to {}: {}",
+                index + 1,
+                index + 2,
+                e
+            )
+        })?;
+    }
+
+    // The version comes first, as when the config is saved
+    let mut upgraded = serde_yaml::Mapping::new();
+    upgraded.insert("version".into(), CONFIG_VERSION.into());
+    if let serde_yaml::Value::Mapping(map) = file {
+        upgraded.extend(
+            map.iter()
+                .filter(|(k, _)| k.as_str() != Some("version"))
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+    }
+    *file = upgraded.into();
+    Ok(version)
+}
+
+// Keep a copy of an older config file before it is saved in the current version.
+fn backup_config(path: &str, version: u32) -> Result<()> {
+    let backup = format!("{}.v{}.bak", path, version);
+    write_atomic(&backup, &fs::read_to_string(path)?, false)?;
+    log::info!(
+        "Upgrading the config file {} to version {}, the previous one is saved in {}",
+        path,
+        CONFIG_VERSION,
+        backup
+    );
+    Ok(())
+}
+
+/// A config file written by an older drg, other than the first one. It may be shared with users
+/// of an older drg, so it is not upgraded.
+#[derive(Debug)]
+struct OlderSharedFile(u32);
+
+impl fmt::Display for OlderSharedFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the config file version {} is not upgraded to version {}, as it may be shared",
+            self.0, CONFIG_VERSION
+        )
+    }
+}
+
+impl std::error::Error for OlderSharedFile {}
+
+// The contexts of a version 1 file, holding their cluster settings and token. The layout is frozen,
+// the settings that are moved as they are to version 2 are kept as YAML.
+#[derive(Deserialize)]
+struct ConfigV1 {
+    #[serde(default)]
+    contexts: Vec<ContextV1>,
+}
+
+#[derive(Deserialize)]
+struct ContextV1 {
+    name: String,
+    drogue_cloud_url: Url,
+    default_app: Option<String>,
+    default_algo: Option<String>,
+    cert_profile: Option<serde_yaml::Value>,
+    key_policy: Option<serde_yaml::Value>,
+    oidc: Option<serde_yaml::Value>,
+    auth_url: Url,
+    token_url: Url,
+    registry_url: Url,
+    token_exp_date: Option<serde_yaml::Value>,
+    // Missing or null when logged out
+    token: Option<serde_yaml::Value>,
+}
+
+// Version 2: the cluster settings and the token of the contexts are moved to separate clusters and
+// users, that contexts refer to by name. The contexts with the same settings share a cluster named
+// after its host, and each context gets a user named after it, unless logged out.
+fn split_contexts(file: &mut serde_yaml::Value) -> Result<()> {
+    use serde_yaml::{Mapping, Value};
+
+    let v1: ConfigV1 = serde_path_to_error::deserialize(file.clone())?;
+
+    let mut clusters: Vec<(String, Mapping)> = Vec::new();
+    let mut users = Vec::new();
+    let mut contexts = Vec::new();
+    for context in v1.contexts {
+        let mut settings = Mapping::new();
+        settings.insert(
+            "drogue_cloud_url".into(),
+            context.drogue_cloud_url.as_str().into(),
+        );
+        if let Some(oidc) = context.oidc {
+            settings.insert("oidc".into(), oidc);
         }
+        settings.insert("auth_url".into(), context.auth_url.as_str().into());
+        settings.insert("token_url".into(), context.token_url.as_str().into());
+        settings.insert("registry_url".into(), context.registry_url.as_str().into());
+
+        let cluster = match clusters.iter().find(|(_, s)| s == &settings) {
+            Some((name, _)) => name.clone(),
+            None => {
+                let host = context.drogue_cloud_url.host_str().unwrap_or("cluster");
+                let mut name = host.to_string();
+                let mut index = 1;
+                while clusters.iter().any(|(n, _)| n == &name) {
+                    index += 1;
+                    name = format!("{}-{}", host, index);
+                }
+                clusters.push((name.clone(), settings));
+                name
+            }
+        };
+
+        let mut entry = Mapping::new();
+        entry.insert("name".into(), context.name.as_str().into());
+        entry.insert("cluster".into(), cluster.into());
+        if let Some(token) = context.token.filter(|t| !t.is_null()) {
+            let mut user = Mapping::new();
+            user.insert("name".into(), context.name.as_str().into());
+            if let Some(exp_date) = context.token_exp_date {
+                user.insert("token_exp_date".into(), exp_date);
+            }
+            user.insert("token".into(), token);
+            users.push(Value::Mapping(user));
+            entry.insert("user".into(), context.name.as_str().into());
+        }
+        entry.insert(
+            "default_app".into(),
+            context.default_app.map_or(Value::Null, Value::from),
+        );
+        entry.insert(
+            "default_algo".into(),
+            context.default_algo.map_or(Value::Null, Value::from),
+        );
+        if let Some(profile) = context.cert_profile {
+            entry.insert("cert_profile".into(), profile);
+        }
+        if let Some(policy) = context.key_policy {
+            entry.insert("key_policy".into(), policy);
+        }
+        contexts.push(Value::Mapping(entry));
+    }
+
+    let clusters = clusters
+        .into_iter()
+        .map(|(name, settings)| {
+            let mut cluster = Mapping::new();
+            cluster.insert("name".into(), name.into());
+            cluster.extend(settings);
+            Value::Mapping(cluster)
+        })
+        .collect::<Vec<_>>();
+    if let Value::Mapping(map) = file {
+        map.insert("clusters".into(), clusters.into());
+        map.insert("users".into(), users.into());
+        map.insert("contexts".into(), contexts.into());
+    }
+    Ok(())
+}
+
+// Advisory lock on a file next to the config, serializing the writes of concurrent drg processes.
+// It is released when the returned file is dropped.
+fn lock_config(path: &str) -> Result<File> {
+    let lock = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .open(format!("{}.lock", path))
+        .context("Cannot create the config lock file")?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::AsRawFd;
+        // Safety: the file descriptor is valid for the lifetime of `lock`
+        if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("Cannot lock the config file");
+        }
+    }
+    Ok(lock)
+}
+
+// The config file is replaced at once, so that it is never seen partially written.
+// A new file holds the tokens, only the user can read it. With `keep_permissions`, a replaced file
+// keeps its permissions and, when allowed, its owner.
+fn write_atomic(path: &str, content: &str, keep_permissions: bool) -> Result<()> {
+    // The target of a symbolic link is replaced, rather than the link
+    let path = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    // Temporary files are created with 0600 permissions
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    if keep_permissions {
+        if let Ok(metadata) = fs::metadata(&path) {
+            file.as_file().set_permissions(metadata.permissions())?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::{fchown, MetadataExt};
+                let owner = fchown(file.as_file(), Some(metadata.uid()), Some(metadata.gid()))
+                    .or_else(|_| fchown(file.as_file(), None, Some(metadata.gid())));
+                if let Err(e) = owner {
+                    log::debug!("Cannot keep the owner of {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+    file.write_all(content.as_bytes())?;
+    file.as_file().sync_all()?;
+    file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+// Several config files can be given, separated as in `$PATH`.
+fn eval_config_paths(path: Option<&str>) -> Vec<String> {
+    let paths: Vec<String> = env::split_paths(&eval_config_path(path))
+        .filter(|p| !p.as_os_str().is_empty())
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect();
+
+    if paths.is_empty() {
+        vec![default_config_path()]
+    } else {
+        paths
     }
 }
 
@@ -383,25 +2030,28 @@ impl TokenProvider for Token {
 fn eval_config_path(path: Option<&str>) -> String {
     match path {
         Some(p) => p.to_string(),
-        None => env::var("DRGCFG").unwrap_or_else(|_| {
-            let xdg = match config_dir() {
-                Some(path) => path.into_os_string().into_string().unwrap(),
-                None => {
-                    log::error!("Error accessing config file, please try using --config");
-                    exit(1);
-                }
-            };
-            format!("{}/drg_config.yaml", xdg)
-        }),
+        None => env::var("DRGCFG").unwrap_or_else(|_| default_config_path()),
     }
 }
 
+fn default_config_path() -> String {
+    let xdg = match config_dir() {
+        Some(path) => path.into_os_string().into_string().unwrap(),
+        None => {
+            log::error!("Error accessing config file, please try using --config");
+            exit(1);
+        }
+    };
+    format!("{}/drg_config.yaml", xdg)
+}
+
 pub fn pretty_list(contexts: &Vec<Context>, active: Option<&String>) {
-    let mut table = Table::new("{:<}  {:<}  {:<}");
+    let mut table = Table::new("{:<}  {:<}  {:<}  {:<}");
     table.add_row(
         Row::new()
             .with_cell("NAME")
             .with_cell("ADDRESS")
+            .with_cell("USER")
             .with_cell("DEFAULT APP"),
     );
 
@@ -415,6 +2065,7 @@ pub fn pretty_list(contexts: &Vec<Context>, active: Option<&String>) {
             Row::new()
                 .with_cell(&name)
                 .with_cell(&config.drogue_cloud_url)
+                .with_cell(config.user.as_deref().unwrap_or("<Not Set>"))
                 .with_cell(
                     &config
                         .default_app
@@ -426,3 +2077,591 @@ pub fn pretty_list(contexts: &Vec<Context>, active: Option<&String>) {
 
     print!("{}", table);
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encrypted_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        let path = path.to_str();
+
+        let mut config = Config::empty();
+        let token = AccessToken {
+            id: "alice".to_string(),
+            token: "drg_secret".to_string(),
+        };
+        let api = Url::parse("https://api.example.net").unwrap();
+        config
+            .add_context(Context::init_with_access_token(
+                "test".to_string(),
+                api,
+                token,
+            ))
+            .unwrap();
+        config.passphrase = Some(Passphrase::new("correct horse"));
+        config.encrypt_tokens().unwrap();
+        config.write(path).unwrap();
+
+        let content = std::fs::read_to_string(path.unwrap()).unwrap();
+        assert!(!content.contains("drg_secret"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(path.unwrap())
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let mut loaded = Config::from(path).unwrap();
+        assert!(loaded.encrypt_tokens);
+        assert!(loaded.get_context(&None).unwrap().token.is_encrypted());
+
+        let redacted = loaded.redacted();
+        assert!(redacted.contexts[0].token.is_encrypted());
+
+        loaded.passphrase = Some(Passphrase::new("correct horse"));
+        let context = loaded.unlock_context(&None).unwrap();
+        assert!(matches!(&context.token, Token::AccessToken(auth) if auth.token == "drg_secret"));
+        assert!(
+            matches!(context.redacted().token, Token::AccessToken(auth) if auth.token == REDACTED)
+        );
+    }
+
+    fn access_token_context(name: &str, token: &str) -> Context {
+        let token = AccessToken {
+            id: "alice".to_string(),
+            token: token.to_string(),
+        };
+        let api = Url::parse("https://api.example.net").unwrap();
+        Context::init_with_access_token(name.to_string(), api, token)
+    }
+
+    #[test]
+    fn test_concurrent_token_refresh() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        let path = path.to_str();
+        let now = Utc::now();
+
+        let mut config = Config::empty();
+        for name in ["a", "b"] {
+            let mut context = access_token_context(name, "initial");
+            context.token_exp_date = now;
+            config.add_context(context).unwrap();
+        }
+        config.write(path).unwrap();
+
+        let mut first = Config::from(path).unwrap();
+        let mut second = Config::from(path).unwrap();
+
+        let context = second.get_context_mut(&Some("a".to_string())).unwrap();
+        context.token = access_token_context("a", "newest").token;
+        context.token_exp_date = now + chrono::Duration::hours(2);
+        second.changed(true);
+        second.write(path).unwrap();
+
+        // Refreshed with an older token, and other changes
+        let context = first.get_context_mut(&Some("a".to_string())).unwrap();
+        context.token = access_token_context("a", "older").token;
+        context.token_exp_date = now + chrono::Duration::hours(1);
+        context.default_app = Some("app-a".to_string());
+        let context = first.get_context_mut(&Some("b".to_string())).unwrap();
+        context.default_app = Some("app-b".to_string());
+        first.changed(true);
+        first.write(path).unwrap();
+
+        let saved = Config::from(path).unwrap();
+        let a = saved.get_context(&Some("a".to_string())).unwrap();
+        assert!(matches!(&a.token, Token::AccessToken(auth) if auth.token == "newest"));
+        assert_eq!(a.token_exp_date, now + chrono::Duration::hours(2));
+        assert_eq!(a.default_app.as_deref(), Some("app-a"));
+        let b = saved.get_context(&Some("b".to_string())).unwrap();
+        assert_eq!(b.default_app.as_deref(), Some("app-b"));
+        assert_eq!(saved.active_context.as_deref(), Some("a"));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_after_other_process() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        let path = path.to_str();
+        let now = Utc::now();
+
+        let mut config = Config::empty();
+        let mut context = access_token_context("a", "initial");
+        context.token_exp_date = now;
+        config.add_context(context).unwrap();
+        config.write(path).unwrap();
+
+        let mut first = Config::from(path).unwrap();
+        let mut second = Config::from(path).unwrap();
+        let context = second.get_context_mut(&None).unwrap();
+        context.token = access_token_context("a", "refreshed").token;
+        context.token_exp_date = now + chrono::Duration::hours(1);
+        second.changed(true);
+        second.write(path).unwrap();
+
+        // The token refreshed by the other process is used instead of refreshing it again
+        let context = first.refresh_context(path, &None).await.unwrap();
+        assert!(matches!(&context.token, Token::AccessToken(auth) if auth.token == "refreshed"));
+        assert!(!first.has_changes());
+    }
+
+    #[test]
+    fn test_invalid_file_not_overwritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        std::fs::write(&path, "contexts: [").unwrap();
+
+        let mut config = Config::empty();
+        config
+            .add_context(access_token_context("a", "token"))
+            .unwrap();
+        assert!(config.write(path.to_str()).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "contexts: [");
+    }
+
+    #[test]
+    fn test_concurrent_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        let path = path.to_str().unwrap().to_string();
+
+        let mut config = Config::empty();
+        for i in 0..8 {
+            let context = access_token_context(&format!("context-{}", i), "token");
+            config.add_context(context).unwrap();
+        }
+        config.write(Some(&path)).unwrap();
+
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let mut config = Config::from(Some(&path)).unwrap();
+                    let context = config.get_context_mut(&Some(format!("context-{}", i)));
+                    context.unwrap().default_app = Some(format!("app-{}", i));
+                    config.changed(true);
+                    config.write(Some(&path)).unwrap();
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let saved = Config::from(Some(&path)).unwrap();
+        for i in 0..8 {
+            let context = saved.get_context(&Some(format!("context-{}", i))).unwrap();
+            assert_eq!(context.default_app, Some(format!("app-{}", i)));
+        }
+    }
+
+    #[test]
+    fn test_layered_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let personal = dir.path().join("personal.yaml");
+        let team = dir.path().join("team.yaml");
+
+        // A shared context, without tokens
+        let mut shared: serde_yaml::Value = serde_yaml::from_str(SHARED_CONTEXTS).unwrap();
+        migrate(&mut shared).unwrap();
+        std::fs::write(&team, serde_yaml::to_string(&shared).unwrap()).unwrap();
+
+        let mut config = Config::empty();
+        config
+            .add_context(access_token_context("mine", "token"))
+            .unwrap();
+        config.write(personal.to_str()).unwrap();
+
+        let paths = format!("{}:{}", personal.display(), team.display());
+        let path = Some(paths.as_str());
+        let mut config = Config::from(path).unwrap();
+        assert_eq!(config.active_context.as_deref(), Some("mine"));
+        assert_eq!(config.contexts.len(), 2);
+
+        let mine = config.get_context(&Some("mine".to_string())).unwrap();
+        assert!(matches!(mine.token, Token::AccessToken(_)));
+        assert_eq!(mine.origin.as_deref(), personal.to_str());
+        let shared = config.get_context(&Some("shared".to_string())).unwrap();
+        assert!(matches!(shared.token, Token::LoggedOut));
+        assert_eq!(shared.origin.as_deref(), team.to_str());
+
+        // Each change goes to the file of the context, new contexts to the first file
+        let shared = config.get_context_mut(&Some("shared".to_string())).unwrap();
+        shared.default_app = Some("other-app".to_string());
+        config
+            .add_context(access_token_context("new", "token"))
+            .unwrap();
+        config.write(path).unwrap();
+
+        let team_content = std::fs::read_to_string(&team).unwrap();
+        assert!(team_content.contains("other-app"));
+        assert!(!team_content.contains("origin"));
+        assert!(!team_content.contains("new"));
+
+        let personal = Config::from(personal.to_str()).unwrap();
+        assert!(personal.contains_context("new"));
+        assert!(!personal.contains_context("shared"));
+
+        let config = Config::from(path).unwrap();
+        assert_eq!(config.contexts.len(), 3);
+        let new = config.get_context(&Some("new".to_string())).unwrap();
+        assert_eq!(
+            new.origin.as_deref(),
+            Some(paths.split(':').next().unwrap())
+        );
+    }
+
+    // Written before clusters and users were split
+    const SHARED_CONTEXTS: &str = r#"
+active_context: shared
+contexts:
+  - name: shared
+    drogue_cloud_url: "https://api.example.net/"
+    default_app: team-app
+    default_algo: ~
+    auth_url: "https://sso.example.net/auth"
+    token_url: "https://sso.example.net/token"
+    registry_url: "https://api.example.net/"
+  - name: mine
+    drogue_cloud_url: "https://api.example.net/"
+    default_app: ~
+    default_algo: ~
+    auth_url: "https://sso.example.net/auth"
+    token_url: "https://sso.example.net/token"
+    registry_url: "https://api.example.net/"
+"#;
+
+    #[test]
+    fn test_older_shared_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let personal = dir.path().join("personal.yaml");
+        let team = dir.path().join("team.yaml");
+        std::fs::write(&team, SHARED_CONTEXTS).unwrap();
+
+        let mut config = Config::empty();
+        config
+            .add_context(access_token_context("mine", "token"))
+            .unwrap();
+        config.write(personal.to_str()).unwrap();
+
+        // The shared file is migrated in memory only, its changes go to the first file
+        let paths = format!("{}:{}", personal.display(), team.display());
+        let path = Some(paths.as_str());
+        let mut config = Config::from(path).unwrap();
+        let shared = config.get_context_mut(&Some("shared".to_string())).unwrap();
+        assert_eq!(shared.default_app.as_deref(), Some("team-app"));
+        shared.default_app = Some("other-app".to_string());
+        config.changed(true);
+        config.write(path).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&team).unwrap(), SHARED_CONTEXTS);
+        assert!(!Path::new(&format!("{}.v1.bak", team.display())).exists());
+        let config = Config::from(path).unwrap();
+        let shared = config.get_context(&Some("shared".to_string())).unwrap();
+        assert_eq!(shared.default_app.as_deref(), Some("other-app"));
+        assert_eq!(shared.origin.as_deref(), personal.to_str());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shared_file_credentials() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let personal = dir.path().join("personal.yaml");
+        let team = dir.path().join("team.yaml");
+        let link = dir.path().join("team-link.yaml");
+
+        // A shared file with encrypted tokens, readable by the team
+        let mut config = Config::empty();
+        config
+            .add_context(access_token_context("shared", "team-token"))
+            .unwrap();
+        config.passphrase = Some(Passphrase::new("correct horse"));
+        config.encrypt_tokens().unwrap();
+        config.write(team.to_str()).unwrap();
+        std::fs::set_permissions(&team, std::fs::Permissions::from_mode(0o644)).unwrap();
+        std::os::unix::fs::symlink(&team, &link).unwrap();
+
+        let mut config = Config::empty();
+        config
+            .add_context(access_token_context("mine", "token"))
+            .unwrap();
+        config.write(personal.to_str()).unwrap();
+
+        let paths = format!("{}:{}", personal.display(), link.display());
+        let path = Some(paths.as_str());
+        let mut config = Config::from(path).unwrap();
+        config.passphrase = Some(Passphrase::new("correct horse"));
+        let shared = config.unlock_context(&Some("shared".to_string())).unwrap();
+        assert!(matches!(&shared.token, Token::AccessToken(auth) if auth.token == "team-token"));
+
+        // Logging in to the shared context, and changing its defaults
+        shared.token = access_token_context("shared", "my-token").token;
+        shared.default_app = Some("team-app".to_string());
+        config.changed(true);
+        config.write(path).unwrap();
+
+        // The shared file keeps its encrypted token, the new one goes to the first file as set there
+        let team_content = std::fs::read_to_string(&team).unwrap();
+        assert!(team_content.contains("team-app"));
+        assert!(!team_content.contains("my-token"));
+        assert!(!team_content.contains("team-token"));
+        let personal_content = std::fs::read_to_string(&personal).unwrap();
+        assert!(personal_content.contains("my-token"));
+        assert!(!personal_content.contains("team-app"));
+
+        assert!(std::fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        let mode = std::fs::metadata(&team).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o644);
+
+        let config = Config::from(path).unwrap();
+        let shared = config.get_context(&Some("shared".to_string())).unwrap();
+        assert!(matches!(&shared.token, Token::AccessToken(auth) if auth.token == "my-token"));
+        assert_eq!(shared.default_app.as_deref(), Some("team-app"));
+        let team = Config::from(team.to_str()).unwrap();
+        assert!(team.encrypt_tokens);
+        assert!(team.contexts[0].token.is_encrypted());
+    }
+
+    #[test]
+    fn test_clusters_and_users() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        let path = path.to_str();
+
+        // Written before clusters and users were split
+        let contexts = vec![
+            access_token_context("a", "first"),
+            access_token_context("b", "second"),
+        ];
+        let legacy = serde_json::json!({ "active_context": "a", "contexts": contexts });
+        std::fs::write(path.unwrap(), serde_yaml::to_string(&legacy).unwrap()).unwrap();
+
+        let mut config = Config::from(path).unwrap();
+        let a = config.get_context(&Some("a".to_string())).unwrap();
+        assert_eq!(a.cluster, "api.example.net");
+        assert_eq!(a.user.as_deref(), Some("a"));
+
+        let outcome = config.set_context("b", None, Some("a"));
+        assert!(outcome.is_ok());
+        assert!(config.set_context("c", Some("unknown"), None).is_err());
+        config.write(path).unwrap();
+
+        let file: serde_json::Value =
+            serde_yaml::from_str(&std::fs::read_to_string(path.unwrap()).unwrap()).unwrap();
+        assert_eq!(file["clusters"].as_array().unwrap().len(), 1);
+        assert_eq!(file["users"].as_array().unwrap().len(), 1);
+        assert_eq!(file["contexts"][1]["user"], "a");
+
+        // A token renewed through a context is used by the others
+        let mut config = Config::from(path).unwrap();
+        let a = config.get_context_mut(&Some("a".to_string())).unwrap();
+        a.token = access_token_context("a", "renewed").token;
+        config.changed(true);
+        config.write(path).unwrap();
+
+        let config = Config::from(path).unwrap();
+        let b = config.get_context(&Some("b".to_string())).unwrap();
+        assert!(matches!(&b.token, Token::AccessToken(auth) if auth.token == "renewed"));
+    }
+
+    #[test]
+    fn test_contexts_keep_their_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        let path = path.to_str();
+
+        // The same drogue cloud with another OpenID client
+        let mut config = Config::empty();
+        config
+            .add_context(access_token_context("a", "first"))
+            .unwrap();
+        let mut other = access_token_context("other", "second");
+        other.oidc = Some(OidcSettings {
+            client_id: Some("self-hosted".to_string()),
+            ..Default::default()
+        });
+        config.add_context(other).unwrap();
+        assert_ne!(config.contexts[0].cluster, config.contexts[1].cluster);
+
+        // Logging in again with a renamed context does not reuse its user
+        config
+            .rename_context("a".to_string(), "x".to_string())
+            .unwrap();
+        config
+            .add_context(access_token_context("a", "third"))
+            .unwrap();
+
+        // The settings changed in one of the contexts of a cluster
+        let x = config.get_context_mut(&Some("x".to_string())).unwrap();
+        x.oidc = Some(OidcSettings {
+            scopes: vec!["extra".to_string()],
+            ..Default::default()
+        });
+        config.write(path).unwrap();
+
+        let config = Config::from(path).unwrap();
+        let other = config.get_context(&Some("other".to_string())).unwrap();
+        assert_eq!(other.oidc().client_id.as_deref(), Some("self-hosted"));
+        let x = config.get_context(&Some("x".to_string())).unwrap();
+        assert_eq!(x.oidc().scopes, vec!["extra".to_string()]);
+        assert!(matches!(&x.token, Token::AccessToken(auth) if auth.token == "first"));
+        let a = config.get_context(&Some("a".to_string())).unwrap();
+        assert!(a.oidc.is_none());
+        assert_ne!(a.user, x.user);
+        assert!(matches!(&a.token, Token::AccessToken(auth) if auth.token == "third"));
+    }
+
+    #[test]
+    fn test_login_again_keeps_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        let path = path.to_str();
+
+        let mut config = Config::empty();
+        let mut context = access_token_context("a", "first");
+        context.default_app = Some("my-app".to_string());
+        context.default_algo = Some("ECDSA384".to_string());
+        context.cert_profile = Some(CertificateProfile {
+            organization: Some("ACME".to_string()),
+            ..Default::default()
+        });
+        context.key_policy = Some(KeyPolicy {
+            min_rsa_bits: Some(3072),
+            ..Default::default()
+        });
+        context.oidc = Some(OidcSettings {
+            client_id: Some("self-hosted".to_string()),
+            ..Default::default()
+        });
+        config.add_context(context).unwrap();
+
+        config
+            .add_context(access_token_context("a", "second"))
+            .unwrap();
+        // Another drogue cloud starts from scratch
+        let mut other = access_token_context("b", "first");
+        other.default_app = Some("other-app".to_string());
+        config.add_context(other).unwrap();
+        let mut moved = access_token_context("b", "second");
+        moved.drogue_cloud_url = Url::parse("https://api.other.net").unwrap();
+        config.add_context(moved).unwrap();
+        config.write(path).unwrap();
+
+        let config = Config::from(path).unwrap();
+        let a = config.get_context(&Some("a".to_string())).unwrap();
+        assert!(matches!(&a.token, Token::AccessToken(auth) if auth.token == "second"));
+        assert_eq!(a.default_app.as_deref(), Some("my-app"));
+        assert_eq!(a.default_algo.as_deref(), Some("ECDSA384"));
+        assert_eq!(
+            a.cert_profile.as_ref().unwrap().organization.as_deref(),
+            Some("ACME")
+        );
+        assert_eq!(a.key_policy.as_ref().unwrap().min_rsa_bits, Some(3072));
+        assert_eq!(a.oidc().client_id.as_deref(), Some("self-hosted"));
+        let b = config.get_context(&Some("b".to_string())).unwrap();
+        assert!(b.default_app.is_none());
+    }
+
+    #[test]
+    fn test_config_migration() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        let path = path.to_str();
+
+        let contexts = vec![access_token_context("a", "first")];
+        let legacy = serde_json::json!({ "active_context": "a", "contexts": contexts });
+        let legacy = serde_yaml::to_string(&legacy).unwrap();
+        std::fs::write(path.unwrap(), &legacy).unwrap();
+
+        // The file is migrated when loaded, and only upgraded once saved
+        let mut config = Config::from(path).unwrap();
+        assert_eq!(
+            config.get_context(&None).unwrap().cluster,
+            "api.example.net"
+        );
+        let backup = format!("{}.v1.bak", path.unwrap());
+        assert_eq!(std::fs::read_to_string(path.unwrap()).unwrap(), legacy);
+        assert!(!Path::new(&backup).exists());
+
+        // The previous file is kept when it is upgraded
+        config.get_context_mut(&None).unwrap().default_app = Some("app".to_string());
+        config.changed(true);
+        config.write(path).unwrap();
+        let file: serde_json::Value =
+            serde_yaml::from_str(&std::fs::read_to_string(path.unwrap()).unwrap()).unwrap();
+        assert_eq!(file["version"], CONFIG_VERSION);
+        assert_eq!(file["contexts"][0]["user"], "a");
+        assert_eq!(file["contexts"][0]["default_app"], "app");
+        assert_eq!(std::fs::read_to_string(backup).unwrap(), legacy);
+
+        // Errors locate the invalid field
+        let invalid = std::fs::read_to_string(path.unwrap())
+            .unwrap()
+            .replace("https://api.example.net/", "not a url");
+        std::fs::write(path.unwrap(), invalid).unwrap();
+        let error = Config::from(path).unwrap_err().to_string();
+        assert!(error.contains("clusters[0].drogue_cloud_url"), "{}", error);
+        assert!(error.contains("line"), "{}", error);
+
+        // As well as in a file to upgrade
+        std::fs::write(
+            path.unwrap(),
+            legacy.replace("https://example.net/", "not a url"),
+        )
+        .unwrap();
+        let error = Config::from(path).unwrap_err().to_string();
+        assert!(error.contains("contexts[0].auth_url"), "{}", error);
+        assert!(error.contains("line"), "{}", error);
+
+        // A file written by a newer drg is not overwritten
+        let newer = format!("version: {}\ncontexts: []\n", CONFIG_VERSION + 1);
+        std::fs::write(path.unwrap(), &newer).unwrap();
+        assert!(Config::from(path).is_err());
+        let mut config = Config::empty();
+        config
+            .add_context(access_token_context("b", "second"))
+            .unwrap();
+        assert!(config.write(path).is_err());
+        assert_eq!(std::fs::read_to_string(path.unwrap()).unwrap(), newer);
+    }
+
+    #[test]
+    fn test_export_import() {
+        let mut config = Config::empty();
+        let mut context = access_token_context("a", "secret");
+        context.default_app = Some("app".to_string());
+        config.add_context(context).unwrap();
+
+        let name = Some("a".to_string());
+        let exported = config.export_context(&name, false).unwrap();
+        let yaml = exported.encode(false).unwrap();
+        assert!(!yaml.contains("secret"), "{}", yaml);
+        let imported = ExportedContext::decode(&yaml)
+            .unwrap()
+            .into_context("b".to_string());
+        assert_eq!(imported.default_app.as_deref(), Some("app"));
+        assert_eq!(
+            imported.drogue_cloud_url.as_str(),
+            "https://api.example.net/"
+        );
+        assert!(matches!(imported.token, Token::LoggedOut));
+
+        let exported = config.export_context(&name, true).unwrap();
+        let blob = exported.encode(true).unwrap();
+        let imported = ExportedContext::decode(&blob)
+            .unwrap()
+            .into_context("b".to_string());
+        assert!(matches!(&imported.token, Token::AccessToken(auth) if auth.token == "secret"));
+    }
+}
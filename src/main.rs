@@ -2,6 +2,7 @@ mod admin;
 mod applications;
 mod apply;
 mod arguments;
+mod audit;
 mod command;
 mod config;
 mod devices;
@@ -92,7 +93,8 @@ async fn process_arguments(matches: ArgMatches) -> Result<i32> {
         .unwrap_or(false);
 
     if command == Action::login.as_ref() {
-        let mut config = config_result.unwrap_or_else(|_| Config::empty());
+        // Logging in creates the config file if needed, an invalid one is reported
+        let mut config = Config::load(config_path)?.unwrap_or_else(Config::empty);
         let code = display_simple(
             arguments::login::subcommand(submatches, &mut config, &context_arg).await,
             json_output,
@@ -105,18 +107,65 @@ async fn process_arguments(matches: ArgMatches) -> Result<i32> {
         return Ok(0);
     }
 
-    let mut config = config_result?;
-
     if command == Action::config.as_ref() {
+        // Importing a context creates the config file if needed, as logging in does
+        let mut config = match submatches.subcommand_name() {
+            Some("import") => Config::load(config_path)?.unwrap_or_else(Config::empty),
+            _ => config_result?,
+        };
         //fixme handle the pretty print: issue #107
         let code =
-            arguments::config::subcommand(submatches, &mut config, &context_arg, json_output)?;
+            arguments::config::subcommand(submatches, &mut config, &context_arg, json_output)
+                .await?;
         config.write(config_path)?;
         return Ok(code);
     }
 
+    let mut config = config_result?;
+
+    if command == Action::logout.as_ref() {
+        let code = display_simple(
+            arguments::logout::subcommand(submatches, &mut config, &context_arg).await,
+            json_output,
+        );
+        config.write(config_path)?;
+        return code;
+    }
+
     // The following commands needs a context and a valid token
-    openid::verify_token_validity(config.get_context_mut(&context_arg)?).await?;
+    config.refresh_context(config_path, &context_arg).await?;
+
+    if command == Action::rotate.as_ref() {
+        let context = config.get_context_mut(&context_arg)?;
+        let name = context.name.clone();
+        let old_prefix = match tokens::rotate(context).await {
+            Ok(prefix) => prefix,
+            Err(e) => return display_simple::<String>(Err(e), json_output),
+        };
+
+        // The new token is saved before the old one is deleted
+        config.changed(true);
+        config.write(config_path)?;
+
+        let context = config.get_context_mut(&context_arg)?;
+        let outcome: Result<Outcome<String>, DrogueError> = match old_prefix {
+            Some(prefix) => match tokens::delete(context, &prefix).await {
+                Ok(_) => Ok(Outcome::SuccessWithMessage(format!(
+                    "Access token of context {} rotated, the previous token {} was deleted",
+                    name, prefix
+                ))),
+                Err(e) => {
+                    log::warn!("The new access token is saved in context {}", name);
+                    Err(e)
+                }
+            },
+            None => Ok(Outcome::SuccessWithMessage(format!(
+                "Access token of context {} rotated. The previous token was not found, delete it with `drg delete token <prefix>`",
+                name
+            ))),
+        };
+        return display_simple(outcome, json_output);
+    }
 
     let context = config.get_context_mut(&context_arg)?;
 
@@ -124,6 +173,19 @@ async fn process_arguments(matches: ArgMatches) -> Result<i32> {
         let (_, submatches) = matches.subcommand().unwrap();
         let code = if submatches.is_present(Parameters::token.as_ref()) {
             display_simple(Ok(openid::print_token(context)), json_output)?
+        } else if submatches.is_present(Parameters::permissions.as_ref()) {
+            let user = submatches.value_of(Parameters::user.as_ref());
+            display(
+                admin::permissions(context, user).await,
+                json_output,
+                admin::permissions_table,
+            )?
+        } else if submatches.is_present(Parameters::claims.as_ref()) {
+            display(
+                openid::session(context).await,
+                json_output,
+                openid::session_print,
+            )?
         } else if let Some((_, endpoints_matches)) = submatches.subcommand() {
             let service = match endpoints_matches.value_of(Parameters::endpoints.as_ref()) {
                 Some("*") => None,
@@ -151,6 +213,7 @@ async fn process_arguments(matches: ArgMatches) -> Result<i32> {
         Action::delete => arguments::delete::subcommand(cmd, context, json_output).await?,
         Action::edit => arguments::edit::subcommand(cmd, context, json_output).await?,
         Action::get => arguments::get::subcommand(cmd, context, json_output).await?,
+        Action::renew => arguments::renew::subcommand(cmd, context, json_output).await?,
         Action::set => {
             let (target, command) = cmd.subcommand().unwrap();
             let app_id = arguments::get_app_id(command, context)?;
@@ -236,12 +299,21 @@ async fn process_arguments(matches: ArgMatches) -> Result<i32> {
                 }
                 Transfer::accept => {
                     let id = cmd.value_of(ResourceId::applicationId.as_ref()).unwrap();
-                    display_simple(admin::accept_transfer(context, id).await, json_output)?
+                    let id = admin::transfer_app_id(id)?;
+                    display_simple(admin::accept_transfer(context, &id).await, json_output)?
                 }
                 Transfer::cancel => {
                     let id = cmd.value_of(ResourceId::applicationId.as_ref()).unwrap();
                     display_simple(admin::cancel_transfer(context, id).await, json_output)?
                 }
+                Transfer::status => {
+                    let id = cmd.value_of(ResourceId::applicationId.as_ref()).unwrap();
+                    display(
+                        admin::transfer_status(context, id).await,
+                        json_output,
+                        admin::transfers_table,
+                    )?
+                }
             }
         }
         Action::stream => {
@@ -261,14 +333,50 @@ async fn process_arguments(matches: ArgMatches) -> Result<i32> {
 
         Action::apply => {
             let (_, matches) = matches.subcommand().unwrap();
-            let path: Vec<&PathBuf> = matches
-                .get_many::<PathBuf>(ResourceType::path.as_ref())
-                .unwrap()
-                .collect();
-            let ignore_version = matches.get_flag(Parameters::ignore_conflict.as_ref());
-
-            let res = apply::apply(context, path, ignore_version).await;
-            display_simple(res, json_output)?
+
+            if let Some((_, members)) = matches.subcommand() {
+                let path: Vec<&PathBuf> = members
+                    .get_many::<PathBuf>(ResourceType::path.as_ref())
+                    .unwrap()
+                    .collect();
+                let prune = members.get_flag(Parameters::prune.as_ref());
+
+                let res = apply::apply_members(context, path, prune).await;
+                display(res, json_output, admin::members_diff_table)?
+            } else {
+                let path: Vec<&PathBuf> = matches
+                    .get_many::<PathBuf>(ResourceType::path.as_ref())
+                    .unwrap()
+                    .collect();
+                let ignore_version = matches.get_flag(Parameters::ignore_conflict.as_ref());
+
+                let res = apply::apply(context, path, ignore_version, json_output).await;
+                display_simple(res, json_output)?
+            }
+        }
+
+        Action::audit => {
+            let (_, command) = cmd.subcommand().unwrap();
+            // Safe unwrap because clap validates the duration and provides a default value
+            let within =
+                humantime::parse_duration(command.value_of(Parameters::within.as_ref()).unwrap())?;
+            let issued_certs = command
+                .get_one::<PathBuf>(Parameters::issued_certs.as_ref())
+                .map(|p| p.as_path());
+
+            let res = audit::audit_certificates(
+                context,
+                chrono::Duration::from_std(within)?,
+                issued_certs,
+            )
+            .await;
+            let found =
+                matches!(&res, Ok(Outcome::SuccessWithJsonData(certs)) if !certs.is_empty());
+
+            match display(res, json_output, audit::expiring_table)? {
+                0 if found => audit::EXPIRING_CERTS_EXIT_CODE,
+                code => code,
+            }
         }
 
         Action::label => {
@@ -1,84 +1,190 @@
 use oauth2::basic::{BasicClient, BasicTokenResponse};
+use oauth2::devicecode::StandardDeviceAuthorizationResponse;
 use oauth2::reqwest::async_http_client;
 use oauth2::{
-    AuthUrl, AuthorizationCode, ClientId, CsrfToken, PkceCodeChallenge, RedirectUrl, Scope,
-    TokenResponse, TokenUrl,
+    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, DeviceAuthorizationUrl,
+    PkceCodeChallenge, RedirectUrl, Scope, TokenResponse, TokenUrl,
 };
 
 use anyhow::Error;
 use anyhow::Result;
+use base64::{engine::general_purpose, Engine as _};
 
 use tiny_http::{Response, Server};
 
 use qstring::QString;
 use reqwest::Url;
 
-use crate::config::{Context, Token};
+use crate::admin::tokens;
+use crate::config::{ClientCredentials, Context, Token};
 use crate::util;
-use crate::util::{show_json, Outcome};
-use chrono::{DateTime, Duration, Utc};
-use serde::Serialize;
-use std::net::{Ipv4Addr, SocketAddr};
+use crate::util::{show_json, DrogueError, Outcome};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 const CLIENT_ID: &str = "drogue";
 
+/// The OpenID client drg authenticates as, when the defaults of drogue cloud do not fit,
+/// e.g. with a self-hosted SSO server.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct OidcSettings {
+    /// Defaults to the "drogue" client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// Requested on top of `offline_access`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+    /// The address the browser login callback listens on, defaults to localhost.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_host: Option<IpAddr>,
+    /// Defaults to a random port.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_port: Option<u16>,
+}
+
+impl OidcSettings {
+    pub fn client_id(&self) -> &str {
+        self.client_id.as_deref().unwrap_or(CLIENT_ID)
+    }
+
+    fn scopes(&self) -> Vec<Scope> {
+        self.scopes.iter().map(|s| Scope::new(s.clone())).collect()
+    }
+}
+
+/// How to authenticate with the OpenID provider of drogue cloud.
+pub enum LoginMethod<'a> {
+    /// Authorization code flow, through a local browser.
+    Browser,
+    /// Device authorization grant, the code is entered in a browser on any device.
+    DeviceCode,
+    RefreshToken(&'a str),
+    /// Client credentials grant, for service accounts.
+    ClientCredentials {
+        client_id: String,
+        client_secret: String,
+    },
+}
+
 pub async fn login(
     api_endpoint: Url,
-    refresh_token_val: Option<&str>,
+    method: LoginMethod<'_>,
+    settings: OidcSettings,
     context_name: String,
 ) -> Result<Context> {
     log::info!("Starting authentication process with {}", api_endpoint);
 
     let (issuer_url, registry_url) = util::get_drogue_endpoints(api_endpoint.clone()).await?;
-    let (auth_url, token_url) = util::get_auth_and_tokens_endpoints(issuer_url).await?;
-    let token = match refresh_token_val {
-        Some(refresh_token_val) => {
-            exchange_token(
+    let (auth_url, token_url) = util::get_auth_and_tokens_endpoints(issuer_url.clone()).await?;
+    let (token, token_exp_date) = match method {
+        LoginMethod::RefreshToken(refresh_token_val) => {
+            let token = exchange_token(
                 auth_url.clone(),
                 token_url.clone(),
                 &oauth2::RefreshToken::new(refresh_token_val.to_string()),
+                settings.client_id(),
             )
-            .await?
+            .await?;
+            let token_exp_date = calculate_token_expiration_date(&token)?;
+            (Token::TokenResponse(token), token_exp_date)
+        }
+        LoginMethod::DeviceCode => {
+            let device_auth_url = util::get_device_authorization_endpoint(issuer_url).await?;
+            let token = get_token_with_device_code(
+                auth_url.clone(),
+                token_url.clone(),
+                device_auth_url,
+                &settings,
+            )
+            .await?;
+            let token_exp_date = calculate_token_expiration_date(&token)?;
+            (Token::TokenResponse(token), token_exp_date)
+        }
+        LoginMethod::Browser => {
+            let token = get_token(auth_url.clone(), token_url.clone(), &settings).await?;
+            let token_exp_date = calculate_token_expiration_date(&token)?;
+            (Token::TokenResponse(token), token_exp_date)
+        }
+        LoginMethod::ClientCredentials {
+            client_id,
+            client_secret,
+        } => {
+            let token = get_token_with_client_credentials(
+                auth_url.clone(),
+                token_url.clone(),
+                &client_id,
+                &client_secret,
+                &settings,
+            )
+            .await?;
+            let token_exp_date = calculate_token_expiration_date(&token)?;
+            let credentials = ClientCredentials {
+                client_id,
+                client_secret,
+                token,
+            };
+            (Token::ClientCredentials(credentials), token_exp_date)
         }
-        None => get_token(auth_url.clone(), token_url.clone()).await?,
     };
 
-    let token_exp_date = calculate_token_expiration_date(&token)?;
-
     log::info!("Token successfully obtained.");
     log::debug!("{:?}", token);
 
     let config = Context {
         name: context_name,
+        cluster: String::new(),
+        user: None,
         drogue_cloud_url: api_endpoint,
         default_app: None,
         default_algo: None,
-        token: Token::TokenResponse(token),
+        cert_profile: None,
+        key_policy: None,
+        oidc: Some(settings).filter(|s| s != &OidcSettings::default()),
+        token,
         token_url,
         auth_url,
         registry_url,
         token_exp_date,
+        origin: None,
     };
 
     Ok(config)
 }
 
-async fn get_token(auth_url: Url, token_url: Url) -> Result<BasicTokenResponse> {
+async fn get_token(
+    auth_url: Url,
+    token_url: Url,
+    settings: &OidcSettings,
+) -> Result<BasicTokenResponse> {
     log::debug!("Using auth url : {}", auth_url);
 
     //start a local server
-    let bind = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
-    let server = Server::http(bind).unwrap();
+    let host = settings
+        .callback_host
+        .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    let bind = SocketAddr::new(host, settings.callback_port.unwrap_or(0));
+    let server =
+        Server::http(bind).map_err(|e| Error::msg(format!("Cannot listen on {}: {}", bind, e)))?;
     let port = server.server_addr().port();
+    let redirect_host = match settings.callback_host {
+        Some(host) if !host.is_loopback() && !host.is_unspecified() => host.to_string(),
+        _ => "localhost".to_string(),
+    };
 
     let client = BasicClient::new(
-        ClientId::new(CLIENT_ID.to_string()),
+        ClientId::new(settings.client_id().to_string()),
         None,
         AuthUrl::new(auth_url.to_string())?,
         Some(TokenUrl::new(token_url.to_string())?),
     )
     // Where the user will be redirected to after the authorization process.
-    .set_redirect_uri(RedirectUrl::new(format!("http://localhost:{}", port))?);
+    .set_redirect_uri(RedirectUrl::new(format!(
+        "http://{}:{}",
+        redirect_host, port
+    ))?);
 
     // Generate a PKCE challenge. As this is a client app a PKCE challenge this is needed to assure confidentiality.
     let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
@@ -87,6 +193,7 @@ async fn get_token(auth_url: Url, token_url: Url) -> Result<BasicTokenResponse>
     let (final_auth_url, csrf_token) = client
         .authorize_url(CsrfToken::new_random)
         .add_scope(Scope::new("offline_access".to_string()))
+        .add_scopes(settings.scopes())
         .set_pkce_challenge(pkce_challenge)
         .url();
 
@@ -132,10 +239,95 @@ async fn get_token(auth_url: Url, token_url: Url) -> Result<BasicTokenResponse>
     token_result.map_err(|e| Error::msg(format!("error while requesting a token: \n{}", e)))
 }
 
+/// Authenticate with the device authorization grant (RFC 8628): the user enters a code on
+/// any device with a browser while drg polls the token endpoint, no local server is needed.
+async fn get_token_with_device_code(
+    auth_url: Url,
+    token_url: Url,
+    device_auth_url: Url,
+    settings: &OidcSettings,
+) -> Result<BasicTokenResponse> {
+    log::debug!("Using device authorization url : {}", device_auth_url);
+
+    let client = BasicClient::new(
+        ClientId::new(settings.client_id().to_string()),
+        None,
+        AuthUrl::new(auth_url.to_string())?,
+        Some(TokenUrl::new(token_url.to_string())?),
+    )
+    .set_device_authorization_url(DeviceAuthorizationUrl::new(device_auth_url.to_string())?);
+
+    let details: StandardDeviceAuthorizationResponse = client
+        .exchange_device_code()?
+        .add_scope(Scope::new("offline_access".to_string()))
+        .add_scopes(settings.scopes())
+        .request_async(async_http_client)
+        .await
+        .map_err(|e| Error::msg(format!("error while requesting a device code: \n{}", e)))?;
+
+    println!(
+        "\nTo authenticate with drogue cloud please browse to: \n{}\nand enter the code: {}",
+        details.verification_uri().as_str(),
+        details.user_code().secret()
+    );
+    if let Some(complete) = details.verification_uri_complete() {
+        println!("\nOr directly browse to: \n{}", complete.secret());
+    }
+
+    log::info!("Waiting for the authorization.");
+    client
+        .exchange_device_access_token(&details)
+        .request_async(async_http_client, tokio::time::sleep, None)
+        .await
+        .map_err(|e| Error::msg(format!("error while requesting a token: \n{}", e)))
+}
+
+async fn get_token_with_client_credentials(
+    auth_url: Url,
+    token_url: Url,
+    client_id: &str,
+    client_secret: &str,
+    settings: &OidcSettings,
+) -> Result<BasicTokenResponse> {
+    log::debug!(
+        "Requesting a service account token using url : {}",
+        token_url
+    );
+
+    let client = BasicClient::new(
+        ClientId::new(client_id.to_string()),
+        Some(ClientSecret::new(client_secret.to_string())),
+        AuthUrl::new(auth_url.to_string())?,
+        Some(TokenUrl::new(token_url.to_string())?),
+    );
+
+    client
+        .exchange_client_credentials()
+        .add_scopes(settings.scopes())
+        .request_async(async_http_client)
+        .await
+        .map_err(|e| Error::msg(format!("error while requesting a token: \n{}", e)))
+}
+
 pub async fn verify_token_validity(context: &mut Context) -> Result<bool> {
+    match context.token {
+        Token::LoggedOut => {
+            return Err(Error::msg(format!(
+                "Context {} is logged out, log in again with `drg login`",
+                context.name
+            )))
+        }
+        Token::Encrypted(_) => {
+            return Err(Error::msg(format!(
+                "The tokens of context {} are encrypted",
+                context.name
+            )))
+        }
+        _ => {}
+    }
+
     log::debug!("Token expires at : {}", context.token_exp_date);
-    // 30 seconds should be enough
-    if context.token_exp_date - Utc::now() > Duration::seconds(30) {
+    if !token_expires_soon(context) {
         Ok(false)
     } else {
         log::info!("Token is expired or will be soon, refreshing...");
@@ -143,7 +335,14 @@ pub async fn verify_token_validity(context: &mut Context) -> Result<bool> {
     }
 }
 
+/// Whether the token of the context has to be refreshed before being used.
+pub fn token_expires_soon(context: &Context) -> bool {
+    // 30 seconds should be enough
+    context.token_exp_date - Utc::now() <= Duration::seconds(30)
+}
+
 async fn refresh_token(context: &mut Context) -> Result<bool> {
+    let settings = context.oidc();
     match &context.token {
         Token::TokenResponse(token) => {
             let refresh_token_var = token
@@ -153,6 +352,7 @@ async fn refresh_token(context: &mut Context) -> Result<bool> {
                 context.auth_url.clone(),
                 context.token_url.clone(),
                 refresh_token_var,
+                settings.client_id(),
             )
             .await?;
 
@@ -164,8 +364,30 @@ async fn refresh_token(context: &mut Context) -> Result<bool> {
 
             Ok(true)
         }
+        // Client credentials tokens come without a refresh token, a new one is requested
+        Token::ClientCredentials(credentials) => {
+            let new_token = get_token_with_client_credentials(
+                context.auth_url.clone(),
+                context.token_url.clone(),
+                &credentials.client_id,
+                &credentials.client_secret,
+                &settings,
+            )
+            .await?;
+
+            context.token_exp_date = calculate_token_expiration_date(&new_token)?;
+            context.token = Token::ClientCredentials(ClientCredentials {
+                token: new_token,
+                ..credentials.clone()
+            });
+
+            log::info!("New token will expire at {}", context.token_exp_date);
+            log::info!("Service account token successfully renewed.");
+
+            Ok(true)
+        }
         // Access Tokens are valid indefinitely
-        Token::AccessToken(_) => Ok(false),
+        Token::AccessToken(_) | Token::LoggedOut | Token::Encrypted(_) => Ok(false),
     }
 }
 
@@ -173,6 +395,7 @@ async fn exchange_token(
     auth_url: Url,
     token_url: Url,
     refresh_token_val: &oauth2::RefreshToken,
+    client_id: &str,
 ) -> Result<BasicTokenResponse> {
     log::debug!("Refreshing token using url : {}", &token_url);
 
@@ -180,7 +403,7 @@ async fn exchange_token(
     let token_url = TokenUrl::new(token_url.to_string())?;
 
     let client = BasicClient::new(
-        ClientId::new(CLIENT_ID.to_string()),
+        ClientId::new(client_id.to_string()),
         None,
         auth_url,
         Some(token_url),
@@ -197,6 +420,104 @@ async fn exchange_token(
         })
 }
 
+/// Revoke the OpenID tokens of the context on the SSO server, then wipe them from the context.
+///
+/// Access tokens are not OpenID tokens, they stay valid until they are deleted.
+pub async fn logout(context: &mut Context) -> Result<()> {
+    let revocation_url = match context.token {
+        Token::TokenResponse(_) | Token::ClientCredentials(_) => {
+            let (issuer_url, _) =
+                util::get_drogue_endpoints(context.drogue_cloud_url.clone()).await?;
+            util::get_revocation_endpoint(issuer_url).await?
+        }
+        Token::AccessToken(_) | Token::LoggedOut | Token::Encrypted(_) => None,
+    };
+
+    revoke_tokens(context, revocation_url.as_ref()).await
+}
+
+async fn revoke_tokens(context: &mut Context, revocation_url: Option<&Url>) -> Result<()> {
+    let public_client = context.oidc().client_id().to_string();
+    let (client_id, client_secret, tokens) = match &context.token {
+        Token::TokenResponse(token) => {
+            let mut tokens = vec![("access_token", token.access_token().secret().clone())];
+            if let Some(refresh) = token.refresh_token() {
+                tokens.insert(0, ("refresh_token", refresh.secret().clone()));
+            }
+            (public_client, None, tokens)
+        }
+        Token::ClientCredentials(credentials) => (
+            credentials.client_id.clone(),
+            Some(credentials.client_secret.clone()),
+            vec![(
+                "access_token",
+                credentials.token.access_token().secret().clone(),
+            )],
+        ),
+        Token::AccessToken(auth) => {
+            log::warn!(
+                "The access token of user {} is still valid, delete it with `drg delete token <prefix>`",
+                auth.id
+            );
+            (public_client, None, Vec::new())
+        }
+        Token::LoggedOut | Token::Encrypted(_) => (public_client, None, Vec::new()),
+    };
+
+    if !tokens.is_empty() {
+        match revocation_url {
+            Some(revocation_url) => {
+                for (hint, token) in tokens {
+                    revoke_token(revocation_url, &client_id, client_secret.as_deref(), &token, hint)
+                        .await?;
+                }
+            }
+            None => log::warn!(
+                "The SSO server does not support token revocation, the tokens of context {} stay valid until they expire",
+                context.name
+            ),
+        }
+    }
+
+    context.token = Token::LoggedOut;
+    context.token_exp_date = Utc::now();
+    Ok(())
+}
+
+// Token revocation request, as per RFC 7009
+async fn revoke_token(
+    revocation_url: &Url,
+    client_id: &str,
+    client_secret: Option<&str>,
+    token: &str,
+    hint: &str,
+) -> Result<()> {
+    log::debug!("Revoking {} using url : {}", hint, revocation_url);
+
+    let mut request = reqwest::Client::new().post(revocation_url.clone());
+    request = match client_secret {
+        Some(secret) => request
+            .basic_auth(client_id, Some(secret))
+            .form(&[("token", token), ("token_type_hint", hint)]),
+        None => request.form(&[
+            ("token", token),
+            ("token_type_hint", hint),
+            ("client_id", client_id),
+        ]),
+    };
+
+    let response = request.send().await?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(Error::msg(format!(
+            "Revoking the {} failed: HTTP {}",
+            hint,
+            response.status()
+        )))
+    }
+}
+
 fn calculate_token_expiration_date(token: &BasicTokenResponse) -> Result<DateTime<Utc>> {
     let now = Utc::now();
     let expiration = token
@@ -209,12 +530,13 @@ fn calculate_token_expiration_date(token: &BasicTokenResponse) -> Result<DateTim
 
 pub fn print_token(context: &Context) -> Outcome<String> {
     match &context.token {
-        Token::TokenResponse(token) => {
+        Token::TokenResponse(token) | Token::ClientCredentials(ClientCredentials { token, .. }) => {
             Outcome::SuccessWithMessage(token.access_token().secret().clone())
         }
         Token::AccessToken(auth) => {
             Outcome::SuccessWithMessage(format!("{}:{}", auth.id, auth.token))
         }
+        Token::LoggedOut | Token::Encrypted(_) => Outcome::SuccessWithMessage(String::new()),
     }
 }
 
@@ -255,3 +577,419 @@ pub fn print_whoami(context: &Context, json: bool) -> Result<i32> {
 
     Ok(0)
 }
+
+/// The name of the user the context is logged in as.
+///
+/// It is the id of an access token, or the preferred username claim of an OpenID token.
+pub fn username(context: &Context) -> Option<String> {
+    match &context.token {
+        Token::AccessToken(auth) => Some(auth.id.clone()),
+        Token::LoggedOut | Token::Encrypted(_) => None,
+        Token::TokenResponse(token) | Token::ClientCredentials(ClientCredentials { token, .. }) => {
+            jwt_claims(token.access_token().secret())?
+                .get("preferred_username")
+                .and_then(|name| name.as_str())
+                .map(|name| name.to_string())
+        }
+    }
+}
+
+/// The claims of a JWT, without verifying its signature.
+fn jwt_claims(token: &str) -> Option<Value> {
+    let payload = token.split('.').nth(1)?;
+    serde_json::from_slice(&general_purpose::URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+}
+
+/// The identity and session of a context, as found in its tokens.
+#[derive(Serialize, Debug, Default)]
+pub struct Session {
+    pub context_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub audience: Vec<String>,
+    /// Realm roles, and client roles as `<client>:<role>`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_expires: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_prefix: Option<String>,
+}
+
+impl Session {
+    fn from_claims(context_name: String, claims: &Value) -> Self {
+        let string = |name: &str| claims[name].as_str().map(|s| s.to_string());
+        let strings = |value: &Value| -> Vec<String> {
+            match value {
+                Value::String(s) => vec![s.clone()],
+                Value::Array(values) => values
+                    .iter()
+                    .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                    .collect(),
+                _ => Vec::new(),
+            }
+        };
+
+        let mut roles = strings(&claims["realm_access"]["roles"]);
+        if let Some(clients) = claims["resource_access"].as_object() {
+            for (client, access) in clients {
+                roles.extend(
+                    strings(&access["roles"])
+                        .into_iter()
+                        .map(|role| format!("{}:{}", client, role)),
+                );
+            }
+        }
+
+        Session {
+            context_name,
+            subject: string("sub"),
+            username: string("preferred_username"),
+            email: string("email"),
+            issuer: string("iss"),
+            audience: strings(&claims["aud"]),
+            roles,
+            groups: strings(&claims["groups"]),
+            expires: claims["exp"].as_i64().map(|exp| Utc.timestamp(exp, 0)),
+            ..Default::default()
+        }
+    }
+}
+
+/// Decode the tokens of the context. For access tokens, the token prefix is looked up in
+/// drogue cloud.
+pub async fn session(context: &Context) -> Result<Outcome<Session>, DrogueError> {
+    let session = match &context.token {
+        Token::TokenResponse(token) | Token::ClientCredentials(ClientCredentials { token, .. }) => {
+            let claims = jwt_claims(token.access_token().secret()).ok_or_else(|| {
+                DrogueError::InvalidInput("The access token is not a valid JWT".to_string())
+            })?;
+            let mut session = Session::from_claims(context.name.clone(), &claims);
+            session.refresh_expires = token
+                .refresh_token()
+                .and_then(|refresh| jwt_claims(refresh.secret()))
+                .and_then(|claims| claims["exp"].as_i64())
+                .map(|exp| Utc.timestamp(exp, 0));
+            session
+        }
+        Token::AccessToken(auth) => Session {
+            context_name: context.name.clone(),
+            username: Some(auth.id.clone()),
+            token_id: Some(auth.id.clone()),
+            token_prefix: tokens::current(context).await?.map(|t| t.prefix),
+            ..Default::default()
+        },
+        Token::LoggedOut | Token::Encrypted(_) => Session {
+            context_name: context.name.clone(),
+            ..Default::default()
+        },
+    };
+
+    Ok(Outcome::SuccessWithJsonData(session))
+}
+
+pub fn session_print(session: &Session) {
+    let optional = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+    let date = |date: &Option<DateTime<Utc>>| {
+        date.map(|d| d.to_rfc3339())
+            .unwrap_or_else(|| "-".to_string())
+    };
+
+    println!("Context name: {}", session.context_name);
+    if session.token_id.is_some() {
+        println!("Access token id: {}", optional(&session.token_id));
+        println!("Access token prefix: {}", optional(&session.token_prefix));
+        return;
+    }
+
+    println!("Subject: {}", optional(&session.subject));
+    println!("Username: {}", optional(&session.username));
+    println!("Email: {}", optional(&session.email));
+    println!("Issuer: {}", optional(&session.issuer));
+    println!("Audience: {}", session.audience.join(", "));
+    println!("Roles: {}", session.roles.join(", "));
+    println!("Groups: {}", session.groups.join(", "));
+    println!("Token expires: {}", date(&session.expires));
+    println!("Refresh token expires: {}", date(&session.refresh_expires));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use tiny_http::Header;
+
+    /// A minimal OpenID provider answering the discovery, device authorization, token and
+    /// revocation requests, until it served the given number of requests. The first device
+    /// access token request is answered as pending authorization.
+    fn mock_provider(requests: usize) -> (Url, thread::JoinHandle<()>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let base = format!("http://127.0.0.1:{}/", server.server_addr().port());
+        let issuer = Url::parse(&base).unwrap();
+
+        let handle = thread::spawn(move || {
+            let mut polls = 0;
+            for _ in 0..requests {
+                let mut request = server.recv().unwrap();
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+
+                let (status, response) = match request.url() {
+                    "/.well-known/openid-configuration" => (
+                        200,
+                        serde_json::json!({
+                            "authorization_endpoint": format!("{base}auth"),
+                            "token_endpoint": format!("{base}token"),
+                            "device_authorization_endpoint": format!("{base}device"),
+                            "revocation_endpoint": format!("{base}revoke"),
+                        }),
+                    ),
+                    "/device" => {
+                        assert!(body.contains("client_id=drogue"));
+                        assert!(body.contains("scope=offline_access+profile"));
+                        (
+                            200,
+                            serde_json::json!({
+                                "device_code": "the-device-code",
+                                "user_code": "ABCD-EFGH",
+                                "verification_uri": format!("{base}device/verify"),
+                                "expires_in": 60,
+                                "interval": 1,
+                            }),
+                        )
+                    }
+                    "/token" if body.contains("grant_type=client_credentials") => {
+                        let expected = format!(
+                            "Basic {}",
+                            general_purpose::STANDARD.encode("drg-ci:the-secret")
+                        );
+                        assert!(request
+                            .headers()
+                            .iter()
+                            .any(|h| h.field.equiv("Authorization") && h.value == expected));
+                        (
+                            200,
+                            serde_json::json!({
+                                "access_token": "the-service-token",
+                                "token_type": "bearer",
+                                "expires_in": 300,
+                            }),
+                        )
+                    }
+                    "/token" => {
+                        assert!(body.contains("device_code=the-device-code"));
+                        polls += 1;
+                        if polls == 1 {
+                            (400, serde_json::json!({"error": "authorization_pending"}))
+                        } else {
+                            (
+                                200,
+                                serde_json::json!({
+                                    "access_token": "the-access-token",
+                                    "refresh_token": "the-refresh-token",
+                                    "token_type": "bearer",
+                                    "expires_in": 300,
+                                }),
+                            )
+                        }
+                    }
+                    "/revoke" => {
+                        assert!(body.contains("client_id=drogue"));
+                        assert!(
+                            body.contains("token=the-refresh-token&token_type_hint=refresh_token")
+                                || body.contains(
+                                    "token=the-access-token&token_type_hint=access_token"
+                                )
+                        );
+                        (200, serde_json::json!({}))
+                    }
+                    other => panic!("Unexpected request to {}", other),
+                };
+
+                let _ = request.respond(
+                    Response::from_string(response.to_string())
+                        .with_status_code(status)
+                        .with_header(
+                            Header::from_bytes("Content-Type", "application/json").unwrap(),
+                        ),
+                );
+            }
+        });
+
+        (issuer, handle)
+    }
+
+    #[tokio::test]
+    async fn test_device_code_login() {
+        let (issuer, provider) = mock_provider(5);
+
+        let (auth_url, token_url) = util::get_auth_and_tokens_endpoints(issuer.clone())
+            .await
+            .unwrap();
+        let device_auth_url = util::get_device_authorization_endpoint(issuer)
+            .await
+            .unwrap();
+
+        let token = get_token_with_device_code(
+            auth_url,
+            token_url,
+            device_auth_url,
+            &OidcSettings {
+                scopes: vec!["profile".to_string()],
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        provider.join().unwrap();
+
+        assert_eq!(token.access_token().secret(), "the-access-token");
+        assert_eq!(
+            token.refresh_token().map(|t| t.secret().as_str()),
+            Some("the-refresh-token")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_credentials_renewal() {
+        let (issuer, provider) = mock_provider(2);
+        let (auth_url, token_url) = util::get_auth_and_tokens_endpoints(issuer.clone())
+            .await
+            .unwrap();
+
+        let expired: BasicTokenResponse = serde_json::from_value(serde_json::json!({
+            "access_token": "the-expired-token",
+            "token_type": "bearer",
+        }))
+        .unwrap();
+        let mut context = Context::init_with_access_token(
+            "ci".to_string(),
+            issuer.clone(),
+            crate::config::AccessToken {
+                id: String::new(),
+                token: String::new(),
+            },
+        );
+        context.fill_urls(auth_url, issuer, token_url);
+        context.token = Token::ClientCredentials(ClientCredentials {
+            client_id: "drg-ci".to_string(),
+            client_secret: "the-secret".to_string(),
+            token: expired,
+        });
+        context.token_exp_date = Utc::now();
+
+        assert!(verify_token_validity(&mut context).await.unwrap());
+        provider.join().unwrap();
+
+        match &context.token {
+            Token::ClientCredentials(credentials) => {
+                assert_eq!(credentials.client_id, "drg-ci");
+                assert_eq!(
+                    credentials.token.access_token().secret(),
+                    "the-service-token"
+                );
+            }
+            _ => panic!("The context should keep the client credentials"),
+        }
+        assert!(context.token_exp_date > Utc::now() + Duration::seconds(200));
+    }
+
+    #[tokio::test]
+    async fn test_logout() {
+        let (issuer, provider) = mock_provider(3);
+        let revocation_url = util::get_revocation_endpoint(issuer.clone()).await.unwrap();
+
+        let token: BasicTokenResponse = serde_json::from_value(serde_json::json!({
+            "access_token": "the-access-token",
+            "refresh_token": "the-refresh-token",
+            "token_type": "bearer",
+        }))
+        .unwrap();
+        let mut context = Context::init_with_access_token(
+            "dev".to_string(),
+            issuer,
+            crate::config::AccessToken {
+                id: String::new(),
+                token: String::new(),
+            },
+        );
+        context.token = Token::TokenResponse(token);
+
+        revoke_tokens(&mut context, revocation_url.as_ref())
+            .await
+            .unwrap();
+        provider.join().unwrap();
+
+        assert!(matches!(context.token, Token::LoggedOut));
+        assert!(verify_token_validity(&mut context).await.is_err());
+
+        // The logged out context is kept in the config file
+        let saved = serde_yaml::to_string(&context).unwrap();
+        let loaded: Context = serde_yaml::from_str(&saved).unwrap();
+        assert!(matches!(loaded.token, Token::LoggedOut));
+    }
+
+    fn jwt(claims: serde_json::Value) -> String {
+        format!(
+            "eyJhbGciOiJub25lIn0.{}.",
+            general_purpose::URL_SAFE_NO_PAD.encode(claims.to_string())
+        )
+    }
+
+    #[tokio::test]
+    async fn test_session_claims() {
+        let token: BasicTokenResponse = serde_json::from_value(serde_json::json!({
+            "access_token": jwt(serde_json::json!({
+                "sub": "2f1a7c",
+                "preferred_username": "alice",
+                "email": "alice@example.com",
+                "iss": "https://sso.example.com/realms/drogue",
+                "aud": ["drogue", "account"],
+                "realm_access": {"roles": ["drogue-user"]},
+                "resource_access": {"account": {"roles": ["view-profile"]}},
+                "groups": ["/ops"],
+                "exp": 1700000000,
+            })),
+            "refresh_token": jwt(serde_json::json!({"exp": 1700086400})),
+            "token_type": "bearer",
+        }))
+        .unwrap();
+        let mut context = Context::init_with_access_token(
+            "dev".to_string(),
+            Url::parse("https://api.example.com").unwrap(),
+            crate::config::AccessToken {
+                id: String::new(),
+                token: String::new(),
+            },
+        );
+        context.token = Token::TokenResponse(token);
+
+        let session = match session(&context).await.unwrap() {
+            Outcome::SuccessWithJsonData(session) => session,
+            Outcome::SuccessWithMessage(_) => panic!("Expected the session details"),
+        };
+
+        assert_eq!(session.subject.as_deref(), Some("2f1a7c"));
+        assert_eq!(session.username.as_deref(), Some("alice"));
+        assert_eq!(session.email.as_deref(), Some("alice@example.com"));
+        assert_eq!(session.audience, vec!["drogue", "account"]);
+        assert_eq!(session.roles, vec!["drogue-user", "account:view-profile"]);
+        assert_eq!(session.groups, vec!["/ops"]);
+        assert_eq!(session.expires, Some(Utc.timestamp(1700000000, 0)));
+        assert_eq!(session.refresh_expires, Some(Utc.timestamp(1700086400, 0)));
+        assert_eq!(username(&context).as_deref(), Some("alice"));
+    }
+}
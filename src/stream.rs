@@ -8,7 +8,7 @@ use std::net::TcpStream;
 use tungstenite::http::Request;
 use tungstenite::{connect, Message};
 
-use crate::config::{Context, RequestBuilderExt, Token};
+use crate::config::{ClientCredentials, Context, RequestBuilderExt, Token};
 use crate::{openid, util};
 use drogue_client::integration::ws::v1::client::Message as Drogue_ws_message;
 use tungstenite::client::IntoClientRequest;
@@ -106,8 +106,11 @@ async fn refresh_token(config: &mut Context) -> Option<String> {
     match openid::verify_token_validity(config).await {
         Ok(false) => None,
         Ok(true) => match &config.token {
-            Token::TokenResponse(token) => Some(token.access_token().secret().clone()),
-            Token::AccessToken(_) => None,
+            Token::TokenResponse(token)
+            | Token::ClientCredentials(ClientCredentials { token, .. }) => {
+                Some(token.access_token().secret().clone())
+            }
+            Token::AccessToken(_) | Token::LoggedOut | Token::Encrypted(_) => None,
         },
         Err(e) => {
             log::error!("Error refreshing token - {e}");
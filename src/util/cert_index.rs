@@ -0,0 +1,252 @@
+use crate::config::Context;
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use strum_macros::AsRefStr;
+use tabular::{Row, Table};
+
+/// Overrides the directory holding the issued certificates indexes.
+pub const CERT_INDEX_ENV: &str = "DRG_CERT_INDEX";
+const INDEX_FILE: &str = "index.yaml";
+
+#[derive(AsRefStr, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum IssuedStatus {
+    Valid,
+    /// A newer certificate was issued for the same device.
+    Superseded,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IssuedCertificate {
+    pub serial: String,
+    pub device: String,
+    pub subject: String,
+    /// SHA-256 of the certificate's subject public key info.
+    pub key_fingerprint: String,
+    pub issued: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+    pub status: IssuedStatus,
+}
+
+/// The device certificates issued for an application, in the spirit of an OpenSSL CA database.
+///
+/// Next to the index, the certificates and the signing requests they were issued from are kept,
+/// the latter allowing to renew a certificate for the same key.
+pub struct CertIndex {
+    dir: PathBuf,
+}
+
+impl CertIndex {
+    pub fn new(dir: PathBuf) -> Self {
+        CertIndex { dir }
+    }
+
+    /// The index of an application, stored under the user's data directory.
+    pub fn for_application(context: &Context, app_id: &str) -> Result<Self> {
+        let base = match env::var(CERT_INDEX_ENV) {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => dirs::data_dir()
+                .ok_or_else(|| anyhow!("Cannot find the user data directory"))?
+                .join("drg")
+                .join("certs"),
+        };
+
+        CertIndex::in_dir(base, context, app_id)
+    }
+
+    // Keyed on the drogue cloud instance, so that renaming the context keeps the index
+    fn in_dir(base: PathBuf, context: &Context, app_id: &str) -> Result<Self> {
+        if app_id.is_empty()
+            || app_id == "."
+            || app_id.contains("..")
+            || app_id.contains(['/', '\\'])
+        {
+            return Err(anyhow!("Invalid application id: {}", app_id));
+        }
+
+        Ok(CertIndex::new(
+            base.join(context.instance_id()).join(app_id),
+        ))
+    }
+
+    pub fn list(&self) -> Result<Vec<IssuedCertificate>> {
+        let path = self.dir.join(INDEX_FILE);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let content = fs::read_to_string(&path)?;
+        serde_yaml::from_str(&content)
+            .with_context(|| format!("Invalid certificate index {}", path.display()))
+    }
+
+    /// The certificate currently valid for a device.
+    pub fn latest(&self, device: &str) -> Result<IssuedCertificate> {
+        self.list()?
+            .into_iter()
+            .filter(|c| c.device == device && c.status == IssuedStatus::Valid)
+            .max_by_key(|c| c.issued)
+            .ok_or_else(|| anyhow!("No certificate was issued for device {}", device))
+    }
+
+    /// The DER encoded signing request a certificate was issued from.
+    pub fn request(&self, cert: &IssuedCertificate) -> Result<Vec<u8>> {
+        let path = self
+            .dir
+            .join("requests")
+            .join(format!("{}.der", cert.serial));
+        fs::read(&path).with_context(|| format!("Cannot read {}", path.display()))
+    }
+
+    /// Record a newly issued certificate, superseding the previous ones of the device.
+    pub fn record(
+        &self,
+        device: &str,
+        certificate: &[u8],
+        request: &[u8],
+    ) -> Result<IssuedCertificate> {
+        let (_, cert) = x509_parser::parse_x509_certificate(certificate)
+            .map_err(|e| anyhow!("Invalid certificate: {}", e))?;
+
+        let issued = IssuedCertificate {
+            serial: cert.tbs_certificate.raw_serial_as_string().replace(':', ""),
+            device: device.to_string(),
+            subject: cert.subject().to_string(),
+            key_fingerprint: fingerprint(cert.tbs_certificate.subject_pki.raw),
+            issued: Utc::now(),
+            not_after: Utc.timestamp(cert.validity().not_after.timestamp(), 0),
+            status: IssuedStatus::Valid,
+        };
+
+        fs::create_dir_all(self.dir.join("certs"))?;
+        fs::create_dir_all(self.dir.join("requests"))?;
+        fs::write(
+            self.dir
+                .join("certs")
+                .join(format!("{}.der", issued.serial)),
+            certificate,
+        )?;
+        fs::write(
+            self.dir
+                .join("requests")
+                .join(format!("{}.der", issued.serial)),
+            request,
+        )?;
+
+        let mut entries = self.list()?;
+        for entry in entries.iter_mut().filter(|e| e.device == device) {
+            entry.status = IssuedStatus::Superseded;
+        }
+        entries.push(issued.clone());
+        fs::write(self.dir.join(INDEX_FILE), serde_yaml::to_string(&entries)?)?;
+
+        log::debug!(
+            "Certificate {} of device {} recorded in {}",
+            issued.serial,
+            device,
+            self.dir.display()
+        );
+        Ok(issued)
+    }
+}
+
+fn fingerprint(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+pub fn issued_table(certs: &Vec<IssuedCertificate>) {
+    if certs.is_empty() {
+        println!("No device certificates were issued for this application.");
+        return;
+    }
+
+    let mut table = Table::new("{:<} | {:<} | {:<} | {:<} | {:<}");
+    table.add_row(
+        Row::new()
+            .with_cell("DEVICE")
+            .with_cell("SERIAL")
+            .with_cell("ISSUED")
+            .with_cell("NOT AFTER")
+            .with_cell("STATUS"),
+    );
+
+    for cert in certs {
+        table.add_row(
+            Row::new()
+                .with_cell(&cert.device)
+                .with_cell(&cert.serial)
+                .with_cell(cert.issued.to_rfc3339())
+                .with_cell(cert.not_after.to_rfc3339())
+                .with_cell(cert.status.as_ref()),
+        );
+    }
+    print!("{}", table);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::AccessToken;
+    use url::Url;
+
+    fn context(name: &str, url: &str) -> Context {
+        Context::init_with_access_token(
+            name.to_string(),
+            Url::parse(url).unwrap(),
+            AccessToken {
+                id: "user".to_string(),
+                token: "token".to_string(),
+            },
+        )
+    }
+
+    #[test]
+    fn test_index_survives_context_rename() {
+        let base = PathBuf::from("certs");
+        let index = CertIndex::in_dir(
+            base.clone(),
+            &context("prod", "https://api.example.com"),
+            "app",
+        )
+        .unwrap();
+        let renamed = CertIndex::in_dir(
+            base.clone(),
+            &context("production", "https://api.example.com"),
+            "app",
+        )
+        .unwrap();
+        assert_eq!(index.dir, renamed.dir);
+        assert_eq!(index.dir, base.join("api.example.com").join("app"));
+
+        let local = CertIndex::in_dir(
+            base.clone(),
+            &context("prod", "http://localhost:8011"),
+            "app",
+        )
+        .unwrap();
+        assert_eq!(local.dir, base.join("localhost_8011").join("app"));
+    }
+
+    #[test]
+    fn test_invalid_app_id() {
+        let context = context("prod", "https://api.example.com");
+        for app_id in ["", ".", "..", "../other", "a/b", "a\\b"] {
+            assert!(
+                CertIndex::in_dir(PathBuf::from("certs"), &context, app_id).is_err(),
+                "{} should be rejected",
+                app_id
+            );
+        }
+    }
+}
@@ -1,28 +1,227 @@
-use crate::util::Outcome;
+use crate::util::{read_passphrase, CertIndex, DrogueError, IssuedCertificate, Outcome};
 use anyhow::{anyhow, Result};
 use chrono::{Duration, Utc};
 use drogue_client::registry::v1::ApplicationSpecTrustAnchorEntry;
+use p12_keystore::{KeyStore, KeyStoreEntry, PrivateKeyChain};
+use pkcs8::der::pem::{self, LineEnding};
+use pkcs8::{pkcs5, DecodePrivateKey, EncryptedPrivateKeyInfo, PrivateKeyInfo};
 use rand::rngs::OsRng;
 use rcgen::{
-    BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa,
-    KeyIdMethod, KeyPair, PKCS_ECDSA_P256_SHA256, PKCS_ECDSA_P384_SHA384, PKCS_ED25519,
+    BasicConstraints, Certificate, CertificateParams, CertificateSigningRequest, CustomExtension,
+    DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa, KeyIdMethod, KeyPair,
+    KeyUsagePurpose, SanType, PKCS_ECDSA_P256_SHA256, PKCS_ECDSA_P384_SHA384, PKCS_ED25519,
     PKCS_RSA_SHA256,
 };
-use rsa::{pkcs8::ToPrivateKey, RsaPrivateKey};
+use rsa::signature::{RandomizedSigner, SignatureEncoding, Verifier};
+use rsa::{
+    pkcs1::DecodeRsaPrivateKey, pkcs8::DecodePublicKey, pkcs8::EncodePrivateKey, pss,
+    traits::PublicKeyParts, RsaPrivateKey, RsaPublicKey,
+};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 use std::fs::File;
 use std::io::Write;
+use std::net::IpAddr;
 use std::{fs, process::exit, str::from_utf8};
 use strum_macros::{AsRefStr, EnumString};
+use x509_parser::certificate::X509Certificate;
+use x509_parser::certification_request::X509CertificationRequest;
+use x509_parser::oid_registry::OID_PKCS1_RSASSAPSS;
+use x509_parser::traits::FromDer;
+use yasna::models::ObjectIdentifier;
+use yasna::Tag;
 
 pub const CERT_VALIDITY_DAYS: i64 = 365;
+const DEFAULT_ORGANIZATION: &str = "Drogue IoT";
+const APP_OU: &str = "Cloud";
+const PBKDF2_ITERATIONS: u32 = 100_000;
+/// Passphrase of the encrypted private keys given as input. drg prompts for it when not set.
+pub const KEY_PASSPHRASE_ENV: &str = "DRG_KEY_PASSPHRASE";
+/// Passphrase protecting the written private keys and PKCS#12 bundles. drg prompts for it when not set.
+pub const EXPORT_PASSPHRASE_ENV: &str = "DRG_EXPORT_PASSPHRASE";
+// OID of the subject alternative name extension
+const OID_SUBJECT_ALT_NAME: &[u64] = &[2, 5, 29, 17];
+// OIDs of the RSASSA-PSS signature algorithm and of its SHA-256 and MGF1 parameters
+const OID_RSASSA_PSS: &[u64] = &[1, 2, 840, 113549, 1, 1, 10];
+const OID_SHA256: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 2, 1];
+const OID_MGF1: &[u64] = &[1, 2, 840, 113549, 1, 1, 8];
+const OID_SHA256_WITH_RSA: &[u64] = &[1, 2, 840, 113549, 1, 1, 11];
 
-#[derive(AsRefStr, EnumString)]
+#[derive(AsRefStr, EnumString, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum SignAlgo {
+    /// ECDSA with the P-256 curve and SHA-256
     ECDSA,
+    /// ECDSA with the P-384 curve and SHA-384
+    ECDSA384,
     EdDSA,
+    /// RSA 2048 bits
     RSA,
-    EdDSA384, // Not a key generation choice, only to support custom key of this type
+    RSA3072,
+    RSA4096,
+    /// RSA 2048 bits, certificates signed with RSASSA-PSS and SHA-256
+    #[strum(serialize = "RSA-PSS")]
+    #[serde(rename = "RSA-PSS")]
+    RSAPSS,
+}
+
+impl SignAlgo {
+    /// The RSA algorithm for a key size.
+    pub fn rsa(bits: usize) -> Result<SignAlgo> {
+        match bits {
+            2048 => Ok(SignAlgo::RSA),
+            3072 => Ok(SignAlgo::RSA3072),
+            4096 => Ok(SignAlgo::RSA4096),
+            _ => Err(anyhow!("Unsupported RSA key size: {}", bits)),
+        }
+    }
+
+    pub fn rsa_bits(&self) -> Option<usize> {
+        match self {
+            SignAlgo::RSA | SignAlgo::RSAPSS => Some(2048),
+            SignAlgo::RSA3072 => Some(3072),
+            SignAlgo::RSA4096 => Some(4096),
+            _ => None,
+        }
+    }
+}
+
+/// Restricts the key algorithms used with a context, to reject weak choices.
+///
+/// It applies to the generated keys as well as to the keys given as input.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyPolicy {
+    /// All the algorithms are allowed when empty. RSA allows every key size.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_algorithms: Vec<SignAlgo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_rsa_bits: Option<usize>,
+}
+
+impl KeyPolicy {
+    pub fn check(&self, algo: SignAlgo, rsa_bits: Option<usize>) -> Result<()> {
+        let rsa_bits = rsa_bits.or_else(|| algo.rsa_bits());
+        let allowed = self.allowed_algorithms.is_empty()
+            || self.allowed_algorithms.iter().any(|allowed| {
+                *allowed == algo || (*allowed == SignAlgo::RSA && rsa_bits.is_some())
+            });
+        if !allowed {
+            return Err(anyhow!(
+                "The {} algorithm is not allowed by the key policy of this context",
+                algo.as_ref()
+            ));
+        }
+
+        match (rsa_bits, self.min_rsa_bits) {
+            (Some(bits), Some(min)) if bits < min => Err(anyhow!(
+                "RSA keys of {} bits are too weak for the key policy of this context, at least {} bits are required",
+                bits,
+                min
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(AsRefStr, EnumString, Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum ExportFormat {
+    pem,
+    der,
+    /// A password protected PKCS#12 bundle with the certificate, its private key and the CA chain.
+    p12,
+}
+
+/// How the generated certificates and private keys are written out.
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    /// Encrypts the private keys as PKCS#8, or protects the PKCS#12 bundle.
+    pub passphrase: Option<String>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            format: ExportFormat::pem,
+            passphrase: None,
+        }
+    }
+}
+
+impl ExportOptions {
+    fn certificate(&self, der: &[u8]) -> Result<Vec<u8>> {
+        match self.format {
+            ExportFormat::der => Ok(der.to_vec()),
+            _ => to_pem("CERTIFICATE", der),
+        }
+    }
+
+    fn certificate_request(&self, der: &[u8]) -> Result<Vec<u8>> {
+        match self.format {
+            ExportFormat::der => Ok(der.to_vec()),
+            _ => to_pem("CERTIFICATE REQUEST", der),
+        }
+    }
+
+    /// Serialize a PKCS#8 private key, encrypting it if a passphrase is set.
+    fn private_key(&self, der: &[u8]) -> Result<Vec<u8>> {
+        let (label, der) = match &self.passphrase {
+            Some(passphrase) => {
+                // PBKDF2 and AES-256-CBC, as OpenSSL does, rather than the scrypt default
+                let salt: [u8; 16] = rand::random();
+                let iv: [u8; 16] = rand::random();
+                let params = pkcs5::pbes2::Parameters::pbkdf2_sha256_aes256cbc(
+                    PBKDF2_ITERATIONS,
+                    &salt,
+                    &iv,
+                )
+                .map_err(|e| anyhow!("Cannot encrypt the private key: {}", e))?;
+                let encrypted = PrivateKeyInfo::try_from(der)
+                    .and_then(|key| key.encrypt_with_params(params, passphrase.as_bytes()))
+                    .map_err(|e| anyhow!("Cannot encrypt the private key: {}", e))?;
+                ("ENCRYPTED PRIVATE KEY", encrypted.as_bytes().to_vec())
+            }
+            None => ("PRIVATE KEY", der.to_vec()),
+        };
+
+        match self.format {
+            ExportFormat::der => Ok(der),
+            _ => to_pem(label, &der),
+        }
+    }
+
+    /// Bundle a private key with its certificate chain, leaf certificate first.
+    fn pkcs12(&self, alias: &str, key: &[u8], chain: &[&[u8]]) -> Result<Vec<u8>> {
+        let passphrase = self
+            .passphrase
+            .as_deref()
+            .ok_or_else(|| anyhow!("A passphrase is required to create a PKCS#12 bundle"))?;
+
+        let chain = chain
+            .iter()
+            .map(|cert| p12_keystore::Certificate::from_der(cert))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow!("Invalid certificate: {}", e))?;
+
+        let mut keystore = KeyStore::new();
+        keystore.add_entry(
+            alias,
+            KeyStoreEntry::PrivateKeyChain(PrivateKeyChain::new(key, alias.as_bytes(), chain)),
+        );
+
+        keystore
+            .writer(passphrase)
+            .write()
+            .map_err(|e| anyhow!("Cannot create the PKCS#12 bundle: {}", e))
+    }
+}
+
+fn to_pem(label: &str, der: &[u8]) -> Result<Vec<u8>> {
+    pem::encode_string(label, LineEnding::LF, der)
+        .map(String::into_bytes)
+        .map_err(|e| anyhow!("Cannot encode {} as PEM: {}", label, e))
 }
 
 #[allow(non_camel_case_types)]
@@ -31,6 +230,123 @@ enum CertificateType {
     device,
 }
 
+#[derive(AsRefStr, EnumString, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(serialize_all = "camelCase")]
+#[serde(rename_all = "camelCase")]
+pub enum KeyUsage {
+    DigitalSignature,
+    ContentCommitment,
+    KeyEncipherment,
+    DataEncipherment,
+    KeyAgreement,
+    KeyCertSign,
+    CrlSign,
+}
+
+#[derive(AsRefStr, EnumString, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(serialize_all = "camelCase")]
+#[serde(rename_all = "camelCase")]
+pub enum ExtendedKeyUsage {
+    ServerAuth,
+    ClientAuth,
+    CodeSigning,
+    EmailProtection,
+    TimeStamping,
+    OcspSigning,
+}
+
+/// Customisation of the subject and extensions of the generated certificates.
+///
+/// Unset fields fall back to drg defaults: an "O=Drogue IoT" subject, a "Drogue Iot" DNS name,
+/// and the server and client authentication extended key usages for devices.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct CertificateProfile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub organization: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locality: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dns_names: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub uris: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ip_addresses: Vec<IpAddr>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub key_usages: Vec<KeyUsage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extended_key_usages: Vec<ExtendedKeyUsage>,
+    /// Path length constraint of the application CA.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_length: Option<u8>,
+}
+
+impl CertificateProfile {
+    /// Returns a new profile where the fields set in `other` override the ones of `self`.
+    pub fn merge(&self, other: CertificateProfile) -> CertificateProfile {
+        fn pick<T>(other: Vec<T>, current: &[T]) -> Vec<T>
+        where
+            T: Clone,
+        {
+            if other.is_empty() {
+                current.to_vec()
+            } else {
+                other
+            }
+        }
+
+        CertificateProfile {
+            organization: other.organization.or_else(|| self.organization.clone()),
+            country: other.country.or_else(|| self.country.clone()),
+            state: other.state.or_else(|| self.state.clone()),
+            locality: other.locality.or_else(|| self.locality.clone()),
+            dns_names: pick(other.dns_names, &self.dns_names),
+            uris: pick(other.uris, &self.uris),
+            ip_addresses: pick(other.ip_addresses, &self.ip_addresses),
+            key_usages: pick(other.key_usages, &self.key_usages),
+            extended_key_usages: pick(other.extended_key_usages, &self.extended_key_usages),
+            path_length: other.path_length.or(self.path_length),
+        }
+    }
+
+    fn has_subject_alt_names(&self) -> bool {
+        !(self.dns_names.is_empty() && self.uris.is_empty() && self.ip_addresses.is_empty())
+    }
+
+    // rcgen cannot encode URIs, so the whole extension is encoded here.
+    fn subject_alt_names_extension(&self) -> CustomExtension {
+        let content = yasna::construct_der(|writer| {
+            writer.write_sequence(|writer| {
+                for dns in &self.dns_names {
+                    writer
+                        .next()
+                        .write_tagged_implicit(yasna::Tag::context(2), |w| w.write_ia5_string(dns));
+                }
+                for uri in &self.uris {
+                    writer
+                        .next()
+                        .write_tagged_implicit(yasna::Tag::context(6), |w| w.write_ia5_string(uri));
+                }
+                for ip in &self.ip_addresses {
+                    let octets = match ip {
+                        IpAddr::V4(ip) => ip.octets().to_vec(),
+                        IpAddr::V6(ip) => ip.octets().to_vec(),
+                    };
+                    writer
+                        .next()
+                        .write_tagged_implicit(yasna::Tag::context(7), |w| w.write_bytes(&octets));
+                }
+            })
+        });
+
+        CustomExtension::from_oid_content(OID_SUBJECT_ALT_NAME, content)
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn create_device_certificate(
     app_id: &str,
@@ -42,23 +358,28 @@ pub fn create_device_certificate(
     key_pair_algorithm: Option<SignAlgo>,
     days: Option<&str>,
     key_input: Option<KeyPair>,
+    profile: &CertificateProfile,
+    export: &ExportOptions,
+    index: &CertIndex,
 ) -> Result<Outcome<String>> {
-    let ca_key_content = KeyPair::from_pem(from_utf8(&read_from_file(ca_key)).unwrap_or_default())
-        .or_else(|_| KeyPair::from_der(&read_from_file(ca_key)))
-        .map_err(|e| anyhow!("Error reading CA key file. {}", e))?;
-
-    let ca_cert_pem = from_utf8(ca_cert)?;
-
-    let ca_certificate = CertificateParams::from_ca_cert_pem(ca_cert_pem, ca_key_content)
-        .map_err(|e| anyhow!("Error: {}", e))?;
-
-    let ca_cert_fin = Certificate::from_params(ca_certificate)?;
-
-    // Checking equality of public keys of Cert from application object and supplied CA key
-    verify_public_key(ca_cert_pem, &ca_cert_fin.serialize_der()?)?;
+    let ca_cert_fin = load_ca_certificate(ca_key, ca_cert)?;
 
     let is_input_key = key_input.is_some();
 
+    match export.format {
+        ExportFormat::p12 if cert_out.is_none() => {
+            return Err(anyhow!(
+                "A PKCS#12 bundle must be written to a file, use --cert_output"
+            ))
+        }
+        ExportFormat::der if cert_out.is_none() || (cert_key.is_none() && !is_input_key) => {
+            return Err(anyhow!(
+                "DER output must be written to files, use --cert_output and --key-output"
+            ))
+        }
+        _ => {}
+    }
+
     let device_csr = generate_certificate(
         CertificateType::device,
         device_id,
@@ -66,51 +387,378 @@ pub fn create_device_certificate(
         key_pair_algorithm,
         days,
         key_input,
+        profile,
     )?;
 
     // Signing the device certificate with CA
-    let device_cert = device_csr.serialize_pem_with_signer(&ca_cert_fin)?;
+    let device_cert = sign_like_issuer(
+        device_csr.serialize_der_with_signer(&ca_cert_fin)?,
+        &ca_cert_fin,
+        ca_cert,
+    )?;
+    let device_key = device_csr.serialize_private_key_der();
+    let ca_chain = pem_certificates(ca_cert)?;
+    verify_chain(&device_cert, &ca_chain)?;
+    // The request allows to renew the certificate for the same key later on.
+    // It is recorded once the certificate is written, so that the index never refers to a lost one.
+    let request = device_csr.serialize_request_der()?;
+
+    if export.format == ExportFormat::p12 {
+        let mut chain = vec![device_cert.as_slice()];
+        chain.extend(ca_chain.iter().map(Vec::as_slice));
+        let bundle = export.pkcs12(device_id, &device_key, &chain)?;
+        // Safe unwrap, the output file was checked above
+        write_to_file(cert_out.unwrap(), &bundle, "Device PKCS#12 bundle")?;
+        index.record(device_id, &device_cert, &request)?;
+
+        return Ok(Outcome::SuccessWithMessage(
+            "Device certificate creation process completed".to_string(),
+        ));
+    }
 
+    let exported_cert = export.certificate(&device_cert)?;
     match cert_out {
-        Some(file_name) => write_to_file(file_name, &device_cert, "Device certificate"),
+        Some(file_name) => write_to_file(file_name, &exported_cert, "Device certificate")?,
         _ => {
             println!("This signed device certificate needs to be presented at the time of authentication.\n");
-            println!("{}", &device_cert)
+            println!("{}", from_utf8(&exported_cert)?)
         }
     };
 
     if !is_input_key {
+        let device_key = export.private_key(&device_key)?;
         match cert_key {
-            Some(file_name) => write_to_file(
-                file_name,
-                &device_csr.serialize_private_key_pem(),
-                "Device private key",
-            ),
+            Some(file_name) => write_to_file(file_name, &device_key, "Device private key")?,
             _ => {
                 println!(
                     "Device private key needs to be presented at the time of authentication.\n"
                 );
-                println!("{}", &device_csr.serialize_private_key_pem())
+                println!("{}", from_utf8(&device_key)?)
             }
         }
     };
+    index.record(device_id, &device_cert, &request)?;
 
     Ok(Outcome::SuccessWithMessage(
         "Device certificate creation process completed".to_string(),
     ))
 }
 
+/// Sign a certificate signing request generated by the device itself.
+///
+/// The CSR self-signature is verified, and its subject must be the one expected
+/// by drogue cloud for this device.
+#[allow(clippy::too_many_arguments)]
+pub fn sign_device_csr(
+    app_id: &str,
+    device_id: &str,
+    ca_key: &str,
+    ca_cert: &[u8],
+    csr: &str,
+    cert_out: Option<&str>,
+    days: Option<&str>,
+    profile: &CertificateProfile,
+    policy: &KeyPolicy,
+    export: &ExportOptions,
+    index: &CertIndex,
+) -> Result<Outcome<String>> {
+    let csr_content = read_from_file(csr);
+    let csr_der = match from_utf8(&csr_content) {
+        Ok(text) if text.starts_with("-----BEGIN") => {
+            pem::decode_vec(text.as_bytes())
+                .map_err(|e| anyhow!("Invalid certificate signing request {}: {}", csr, e))?
+                .1
+        }
+        _ => csr_content,
+    };
+
+    sign_device_request(
+        app_id, device_id, ca_key, ca_cert, &csr_der, cert_out, days, profile, policy, export,
+        index,
+    )?;
+
+    Ok(Outcome::SuccessWithMessage(
+        "Device certificate signing process completed".to_string(),
+    ))
+}
+
+/// Issue a new certificate for the key of the last certificate issued to the device.
+#[allow(clippy::too_many_arguments)]
+pub fn renew_device_certificate(
+    app_id: &str,
+    device_id: &str,
+    ca_key: &str,
+    ca_cert: &[u8],
+    cert_out: Option<&str>,
+    days: Option<&str>,
+    profile: &CertificateProfile,
+    policy: &KeyPolicy,
+    export: &ExportOptions,
+    index: &CertIndex,
+) -> Result<Outcome<String>> {
+    let previous = index.latest(device_id)?;
+    let request = index.request(&previous)?;
+
+    // The policy may have changed since the key was certified
+    let renewed = sign_device_request(
+        app_id, device_id, ca_key, ca_cert, &request, cert_out, days, profile, policy, export,
+        index,
+    )?;
+
+    Ok(Outcome::SuccessWithMessage(format!(
+        "Device certificate {} renewed as {}, valid until {}",
+        previous.serial,
+        renewed.serial,
+        renewed.not_after.to_rfc3339()
+    )))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn sign_device_request(
+    app_id: &str,
+    device_id: &str,
+    ca_key: &str,
+    ca_cert: &[u8],
+    csr_der: &[u8],
+    cert_out: Option<&str>,
+    days: Option<&str>,
+    profile: &CertificateProfile,
+    policy: &KeyPolicy,
+    export: &ExportOptions,
+    index: &CertIndex,
+) -> Result<IssuedCertificate> {
+    match export.format {
+        ExportFormat::p12 => {
+            return Err(anyhow!(
+            "A PKCS#12 bundle needs the private key, which stays on the device when signing a CSR"
+        ))
+        }
+        ExportFormat::der if cert_out.is_none() => {
+            return Err(anyhow!(
+                "DER output must be written to a file, use --cert_output"
+            ))
+        }
+        _ => {}
+    }
+
+    let ca_cert_fin = load_ca_certificate(ca_key, ca_cert)?;
+
+    // rcgen verifies the CSR signature while parsing it
+    let mut request = CertificateSigningRequest::from_der(csr_der)
+        .map_err(|e| anyhow!("Invalid certificate signing request: {}", e))?;
+
+    let (algo, rsa_bits) = request_key_algorithm(csr_der)?;
+    policy.check(algo, rsa_bits)?;
+
+    let expected = subject_dn(device_id, app_id, profile);
+    if !same_dn(&request.params.distinguished_name, &expected) {
+        return Err(anyhow!(
+            "The CSR subject \"{}\" does not match the expected subject \"{}\"",
+            format_dn(&request.params.distinguished_name),
+            format_dn(&expected)
+        ));
+    }
+
+    let valid_for = validity_days(days)?;
+
+    let params = &mut request.params;
+    params.not_before = Utc::now();
+    params.not_after = Utc::now() + Duration::days(valid_for);
+    params.serial_number = Some(rand::random());
+    // The SANs requested in the CSR are kept, unless the profile defines its own.
+    if profile.has_subject_alt_names() {
+        params.subject_alt_names.clear();
+    }
+    apply_profile_extensions(params, CertificateType::device, profile);
+
+    let device_cert = sign_like_issuer(
+        request.serialize_der_with_signer(&ca_cert_fin)?,
+        &ca_cert_fin,
+        ca_cert,
+    )?;
+    verify_chain(&device_cert, &pem_certificates(ca_cert)?)?;
+    let exported_cert = export.certificate(&device_cert)?;
+
+    match cert_out {
+        Some(file_name) => write_to_file(file_name, &exported_cert, "Device certificate")?,
+        _ => {
+            println!("This signed device certificate needs to be presented at the time of authentication.\n");
+            println!("{}", from_utf8(&exported_cert)?)
+        }
+    };
+
+    // Recorded once written, so that the index never refers to a lost certificate
+    index.record(device_id, &device_cert, csr_der)
+}
+
+/// The alias drogue cloud uses to match a device with the subject of its certificate.
+///
+/// It lists the common name first, then the other components of the generated subject.
+pub fn device_alias(device_id: &str, app_id: &str, profile: &CertificateProfile) -> String {
+    let dn = subject_dn(device_id, app_id, profile);
+    let mut components = vec![format!("CN={}", device_id)];
+    components.extend(
+        dn.iter()
+            .filter(|(ty, _)| **ty != DnType::CommonName)
+            .map(|(ty, value)| dn_component(ty, value)),
+    );
+
+    components.join(", ")
+}
+
+fn subject_dn(
+    common_name: &str,
+    organizational_unit: &str,
+    profile: &CertificateProfile,
+) -> DistinguishedName {
+    let mut dn = DistinguishedName::new();
+    dn.push(
+        DnType::OrganizationName,
+        profile
+            .organization
+            .as_deref()
+            .unwrap_or(DEFAULT_ORGANIZATION),
+    );
+    dn.push(DnType::OrganizationalUnitName, organizational_unit);
+    if let Some(country) = &profile.country {
+        dn.push(DnType::CountryName, country);
+    }
+    if let Some(state) = &profile.state {
+        dn.push(DnType::StateOrProvinceName, state);
+    }
+    if let Some(locality) = &profile.locality {
+        dn.push(DnType::LocalityName, locality);
+    }
+    dn.push(DnType::CommonName, common_name);
+    dn
+}
+
+fn apply_profile_extensions(
+    params: &mut CertificateParams,
+    cert_type: CertificateType,
+    profile: &CertificateProfile,
+) {
+    if profile.has_subject_alt_names() {
+        params
+            .custom_extensions
+            .push(profile.subject_alt_names_extension());
+    }
+
+    params.key_usages = profile
+        .key_usages
+        .iter()
+        .map(|usage| match usage {
+            KeyUsage::DigitalSignature => KeyUsagePurpose::DigitalSignature,
+            KeyUsage::ContentCommitment => KeyUsagePurpose::ContentCommitment,
+            KeyUsage::KeyEncipherment => KeyUsagePurpose::KeyEncipherment,
+            KeyUsage::DataEncipherment => KeyUsagePurpose::DataEncipherment,
+            KeyUsage::KeyAgreement => KeyUsagePurpose::KeyAgreement,
+            KeyUsage::KeyCertSign => KeyUsagePurpose::KeyCertSign,
+            KeyUsage::CrlSign => KeyUsagePurpose::CrlSign,
+        })
+        .collect();
+
+    params.extended_key_usages = profile
+        .extended_key_usages
+        .iter()
+        .map(|usage| match usage {
+            ExtendedKeyUsage::ServerAuth => ExtendedKeyUsagePurpose::ServerAuth,
+            ExtendedKeyUsage::ClientAuth => ExtendedKeyUsagePurpose::ClientAuth,
+            ExtendedKeyUsage::CodeSigning => ExtendedKeyUsagePurpose::CodeSigning,
+            ExtendedKeyUsage::EmailProtection => ExtendedKeyUsagePurpose::EmailProtection,
+            ExtendedKeyUsage::TimeStamping => ExtendedKeyUsagePurpose::TimeStamping,
+            ExtendedKeyUsage::OcspSigning => ExtendedKeyUsagePurpose::OcspSigning,
+        })
+        .collect();
+
+    match cert_type {
+        CertificateType::app => {
+            // A CA restricted to other usages would not be able to sign the device certificates
+            if !params.key_usages.is_empty() {
+                for usage in [KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign] {
+                    if !params.key_usages.contains(&usage) {
+                        params.key_usages.push(usage);
+                    }
+                }
+            }
+
+            params.is_ca = match profile.path_length {
+                Some(length) => IsCa::Ca(BasicConstraints::Constrained(length)),
+                None => IsCa::Ca(BasicConstraints::Unconstrained),
+            };
+        }
+        CertificateType::device => {
+            if params.extended_key_usages.is_empty() {
+                params.extended_key_usages = vec![
+                    ExtendedKeyUsagePurpose::ServerAuth,
+                    ExtendedKeyUsagePurpose::ClientAuth,
+                ];
+            }
+            params.is_ca = IsCa::SelfSignedOnly;
+            params.key_identifier_method = KeyIdMethod::Sha256;
+        }
+    };
+}
+
+fn same_dn(a: &DistinguishedName, b: &DistinguishedName) -> bool {
+    a.iter().count() == b.iter().count() && a.iter().all(|(ty, value)| b.get(ty) == Some(value))
+}
+
+fn format_dn(dn: &DistinguishedName) -> String {
+    dn.iter()
+        .map(|(ty, value)| dn_component(ty, value))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn dn_component(ty: &DnType, value: &str) -> String {
+    let ty = match ty {
+        DnType::CommonName => "CN".to_string(),
+        DnType::OrganizationName => "O".to_string(),
+        DnType::OrganizationalUnitName => "OU".to_string(),
+        DnType::CountryName => "C".to_string(),
+        DnType::StateOrProvinceName => "ST".to_string(),
+        DnType::LocalityName => "L".to_string(),
+        other => format!("{:?}", other),
+    };
+    format!("{}={}", ty, value)
+}
+
+// Load the application CA certificate and make sure the provided private key matches it.
+fn load_ca_certificate(ca_key: &str, ca_cert: &[u8]) -> Result<Certificate> {
+    let ca_key_content =
+        load_key_pair(ca_key).map_err(|e| anyhow!("Error reading CA key file. {}", e))?;
+
+    let ca_cert_pem = from_utf8(ca_cert)?;
+
+    let ca_der = first_certificate(ca_cert)?;
+    let ca_certificate = if signed_with_pss(&ca_der)? {
+        CertificateParams::from_ca_cert_der(&with_pkcs1_algorithm(&ca_der)?, ca_key_content)
+    } else {
+        CertificateParams::from_ca_cert_pem(ca_cert_pem, ca_key_content)
+    }
+    .map_err(|e| anyhow!("Error: {}", e))?;
+
+    let ca_cert_fin = Certificate::from_params(ca_certificate)?;
+
+    // Checking equality of public keys of Cert from application object and supplied CA key
+    verify_public_key(ca_cert_pem, &ca_cert_fin.serialize_der()?)?;
+
+    Ok(ca_cert_fin)
+}
+
 pub fn verify_input_key(key_input: &str) -> Result<(KeyPair, SignAlgo)> {
-    let key = KeyPair::from_der(&read_from_file(key_input))?;
+    let key = load_key_pair(key_input)?;
 
     let alg = if key.is_compatible(&PKCS_RSA_SHA256) {
-        SignAlgo::RSA
+        // Other key sizes are signed the same way
+        SignAlgo::rsa(rsa_key_bits(&key)?).unwrap_or(SignAlgo::RSA)
     } else if key.is_compatible(&PKCS_ECDSA_P256_SHA256) {
         SignAlgo::ECDSA
     } else if key.is_compatible(&PKCS_ED25519) {
         SignAlgo::EdDSA
     } else if key.is_compatible(&PKCS_ECDSA_P384_SHA384) {
-        SignAlgo::EdDSA384
+        SignAlgo::ECDSA384
     } else {
         return Err(anyhow!("Unknown signature algorithm."));
     };
@@ -118,6 +766,154 @@ pub fn verify_input_key(key_input: &str) -> Result<(KeyPair, SignAlgo)> {
     Ok((key, alg))
 }
 
+// The algorithm of the key a CSR was generated for, with the size of RSA keys.
+fn request_key_algorithm(csr_der: &[u8]) -> Result<(SignAlgo, Option<usize>)> {
+    use x509_parser::oid_registry::{
+        OID_EC_P256, OID_KEY_TYPE_EC_PUBLIC_KEY, OID_NIST_EC_P384, OID_PKCS1_RSAENCRYPTION,
+        OID_SIG_ED25519,
+    };
+
+    let (_, request) = X509CertificationRequest::from_der(csr_der)
+        .map_err(|e| anyhow!("Invalid certificate signing request: {}", e))?;
+    let key = &request.certification_request_info.subject_pki;
+    let algorithm = &key.algorithm.algorithm;
+
+    if *algorithm == OID_PKCS1_RSAENCRYPTION {
+        let bits = RsaPublicKey::from_public_key_der(key.raw)
+            .map_err(|e| anyhow!("Invalid RSA key in the certificate signing request: {}", e))?
+            .size()
+            * 8;
+        Ok((SignAlgo::rsa(bits).unwrap_or(SignAlgo::RSA), Some(bits)))
+    } else if *algorithm == OID_SIG_ED25519 {
+        Ok((SignAlgo::EdDSA, None))
+    } else if *algorithm == OID_KEY_TYPE_EC_PUBLIC_KEY {
+        let curve = key
+            .algorithm
+            .parameters
+            .as_ref()
+            .and_then(|p| p.as_oid().ok());
+        match curve {
+            Some(curve) if *curve == OID_EC_P256 => Ok((SignAlgo::ECDSA, None)),
+            Some(curve) if *curve == OID_NIST_EC_P384 => Ok((SignAlgo::ECDSA384, None)),
+            _ => Err(anyhow!(
+                "Unsupported EC key in the certificate signing request, only P-256 and P-384 curves are supported"
+            )),
+        }
+    } else {
+        Err(anyhow!(
+            "Unsupported key algorithm in the certificate signing request: {}",
+            algorithm
+        ))
+    }
+}
+
+/// The size of an RSA key pair.
+pub fn rsa_key_bits(key: &KeyPair) -> Result<usize> {
+    let key = RsaPrivateKey::from_pkcs8_der(&key.serialize_der())?;
+    Ok(key.size() * 8)
+}
+
+/// Load a private key from a PEM or DER file.
+///
+/// Supported formats are PKCS#8, encrypted PKCS#8, PKCS#1 for RSA keys and SEC1 for EC keys.
+pub fn load_key_pair(file_name: &str) -> Result<KeyPair> {
+    let content = read_from_file(file_name);
+
+    let (label, der) = match from_utf8(&content) {
+        Ok(text) if text.contains("-----BEGIN") => {
+            let block = private_key_pem_block(text)
+                .ok_or_else(|| anyhow!("No private key found in {}", file_name))?;
+            let (label, der) = pem::decode_vec(block.as_bytes())
+                .map_err(|e| anyhow!("Invalid PEM in {}: {}", file_name, e))?;
+            (Some(label.to_string()), der)
+        }
+        _ => (None, content),
+    };
+
+    match label.as_deref() {
+        Some("PRIVATE KEY") => key_pair_from_pkcs8(&der),
+        Some("ENCRYPTED PRIVATE KEY") => decrypt_key_pair(file_name, &der),
+        Some("RSA PRIVATE KEY") => key_pair_from_pkcs1(&der),
+        Some("EC PRIVATE KEY") => key_pair_from_sec1(&der),
+        Some(label) => Err(anyhow!("Unsupported private key type: {}", label)),
+        // DER does not tell the format, try them all
+        None => key_pair_from_pkcs8(&der)
+            .or_else(|_| key_pair_from_pkcs1(&der))
+            .or_else(|_| key_pair_from_sec1(&der))
+            .or_else(
+                |e| match EncryptedPrivateKeyInfo::try_from(der.as_slice()) {
+                    Ok(_) => decrypt_key_pair(file_name, &der),
+                    Err(_) => Err(e),
+                },
+            ),
+    }
+    .map_err(|e| anyhow!("Cannot read private key {}: {}", file_name, e))
+}
+
+// EC keys generated by openssl are preceded by an EC PARAMETERS block
+fn private_key_pem_block(text: &str) -> Option<&str> {
+    text.match_indices("-----BEGIN ").find_map(|(start, _)| {
+        let block = &text[start..];
+        let header = block.lines().next()?;
+        if !header.ends_with("PRIVATE KEY-----") {
+            return None;
+        }
+        let footer = header.replacen("BEGIN", "END", 1);
+        block.find(&footer).map(|end| &block[..end + footer.len()])
+    })
+}
+
+fn key_pair_from_pkcs8(der: &[u8]) -> Result<KeyPair> {
+    let error = match KeyPair::from_der(der) {
+        Ok(key) => return Ok(key),
+        Err(e) => e,
+    };
+
+    // ring does not accept EC keys with the curve parameters repeated in the key itself,
+    // re-encoding them leaves them out.
+    let pkcs8 = if let Ok(key) = p256::SecretKey::from_pkcs8_der(der) {
+        key.to_pkcs8_der()?
+    } else if let Ok(key) = p384::SecretKey::from_pkcs8_der(der) {
+        key.to_pkcs8_der()?
+    } else {
+        return Err(anyhow!("{}", error));
+    };
+
+    KeyPair::from_der(pkcs8.as_bytes()).map_err(|e| anyhow!("{}", e))
+}
+
+fn key_pair_from_pkcs1(der: &[u8]) -> Result<KeyPair> {
+    let pkcs8 = RsaPrivateKey::from_pkcs1_der(der)?.to_pkcs8_der()?;
+    key_pair_from_pkcs8(pkcs8.as_bytes())
+}
+
+fn key_pair_from_sec1(der: &[u8]) -> Result<KeyPair> {
+    let pkcs8 = if let Ok(key) = p256::SecretKey::from_sec1_der(der) {
+        key.to_pkcs8_der()?
+    } else if let Ok(key) = p384::SecretKey::from_sec1_der(der) {
+        key.to_pkcs8_der()?
+    } else {
+        return Err(anyhow!(
+            "Unsupported EC key, only P-256 and P-384 curves are supported"
+        ));
+    };
+
+    key_pair_from_pkcs8(pkcs8.as_bytes())
+}
+
+fn decrypt_key_pair(file_name: &str, der: &[u8]) -> Result<KeyPair> {
+    let passphrase = read_passphrase(
+        KEY_PASSPHRASE_ENV,
+        &format!("Passphrase for {}: ", file_name),
+        false,
+    )?;
+
+    let pkcs8 = EncryptedPrivateKeyInfo::try_from(der)
+        .and_then(|key| key.decrypt(passphrase))
+        .map_err(|_| anyhow!("Cannot decrypt the private key, wrong passphrase?"))?;
+    key_pair_from_pkcs8(pkcs8.as_bytes())
+}
+
 fn verify_public_key(ca_cert: &str, local_cert: &[u8]) -> Result<()> {
     let ca_x509 = x509_parser::pem::parse_x509_pem(ca_cert.as_bytes())?.1;
     let ca_x509_der = x509_parser::parse_x509_certificate(&ca_x509.contents)?.1;
@@ -144,21 +940,217 @@ fn verify_public_key(ca_cert: &str, local_cert: &[u8]) -> Result<()> {
     }
 }
 
-fn write_to_file(file_name: &str, content: &str, resource_type: &str) {
-    let mut file = File::create(file_name);
-    match file.as_mut() {
-        Ok(file) => match file.write_all(content.as_bytes()) {
-            Ok(_) => {
-                log::debug!(
-                    "{} was successfully written to file {}.",
-                    resource_type,
-                    file_name
-                );
+/// The DER encoded certificates of a PEM file, in order.
+fn pem_certificates(content: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut certificates = Vec::new();
+    for pem in x509_parser::pem::Pem::iter_from_buffer(content) {
+        let pem = pem.map_err(|e| anyhow!("Invalid PEM certificate: {}", e))?;
+        if pem.label == "CERTIFICATE" {
+            certificates.push(pem.contents);
+        }
+    }
+
+    if certificates.is_empty() {
+        return Err(anyhow!("No certificate found"));
+    }
+    Ok(certificates)
+}
+
+/// Verify that a certificate is signed by the first certificate of the chain, and that each
+/// certificate of the chain is in turn signed by the next one.
+///
+/// Expired certificates only raise a warning, the chain is left for drogue cloud to reject.
+fn verify_chain(certificate: &[u8], chain: &[Vec<u8>]) -> Result<()> {
+    let mut certificates = vec![x509_parser::parse_x509_certificate(certificate)?.1];
+    for cert in chain {
+        certificates.push(x509_parser::parse_x509_certificate(cert)?.1);
+    }
+
+    for (i, cert) in certificates.iter().enumerate() {
+        if !cert.validity().is_valid() {
+            log::warn!(
+                "Certificate \"{}\" is not valid at this time",
+                cert.subject()
+            );
+        }
+
+        let issuer = match certificates.get(i + 1) {
+            Some(issuer) => issuer,
+            // The root of the chain, if it was provided, signs itself
+            None if cert.subject().to_string() == cert.issuer().to_string() => cert,
+            None => break,
+        };
+
+        // Names are compared as text, as re-encoding them may change their string types
+        if cert.issuer().to_string() != issuer.subject().to_string()
+            || !issuer.tbs_certificate.is_ca()
+        {
+            return Err(anyhow!(
+                "Certificate \"{}\" is not issued by the CA \"{}\"",
+                cert.subject(),
+                issuer.subject()
+            ));
+        }
+        verify_signature(cert, issuer).map_err(|e| {
+            anyhow!(
+                "Invalid signature of \"{}\" by \"{}\": {}",
+                cert.subject(),
+                issuer.subject(),
+                e
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+// x509-parser does not verify RSASSA-PSS signatures, they are checked with the rsa crate
+fn verify_signature(cert: &X509Certificate, issuer: &X509Certificate) -> Result<()> {
+    if cert.signature_algorithm.algorithm != OID_PKCS1_RSASSAPSS {
+        return Ok(cert.verify_signature(Some(issuer.public_key()))?);
+    }
+
+    let key = RsaPublicKey::from_public_key_der(issuer.public_key().raw)?;
+    let signature = pss::Signature::try_from(cert.signature_value.data)?;
+    pss::VerifyingKey::<Sha256>::new(key).verify(cert.tbs_certificate.as_ref(), &signature)?;
+    Ok(())
+}
+
+// rcgen cannot produce RSA-PSS signatures: the certificate it signed with PKCS#1 v1.5
+// is signed again with the same RSA key, using SHA-256, MGF1 with SHA-256 and a 32 bytes salt.
+fn sign_pss(certificate: &[u8], private_key: &[u8]) -> Result<Vec<u8>> {
+    let parts = der_sequence(certificate)?;
+    let mut tbs = der_sequence(&parts[0])?;
+    // The signature algorithm follows the version and the serial number
+    if tbs.len() < 3 || tbs[0].first() != Some(&0xa0) {
+        return Err(anyhow!(
+            "Only X.509 v3 certificates can be signed with RSA-PSS"
+        ));
+    }
+    tbs[2] = pss_algorithm();
+    let tbs = to_der_sequence(&tbs);
+
+    let key = RsaPrivateKey::from_pkcs8_der(private_key)?;
+    let signature = pss::BlindedSigningKey::<Sha256>::new(key)
+        .sign_with_rng(&mut OsRng, &tbs)
+        .to_vec();
+    let signature =
+        yasna::construct_der(|writer| writer.write_bitvec_bytes(&signature, signature.len() * 8));
+
+    Ok(to_der_sequence(&[tbs, pss_algorithm(), signature]))
+}
+
+// The elements of a DER sequence, still encoded.
+fn der_sequence(der: &[u8]) -> Result<Vec<Vec<u8>>> {
+    yasna::parse_der(der, |reader| {
+        reader.collect_sequence_of(|reader| reader.read_der())
+    })
+    .map_err(|e| anyhow!("Invalid certificate: {}", e))
+}
+
+fn to_der_sequence(parts: &[Vec<u8>]) -> Vec<u8> {
+    yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            for part in parts {
+                writer.next().write_der(part);
             }
-            Err(e) => log::error!("Error writing to file: {}", e),
-        },
-        Err(e) => log::error!("Error opening the file: {}", e),
-    };
+        })
+    })
+}
+
+// rcgen signs with the signature algorithm of the CA certificate and does not know RSA-PSS:
+// it is given the certificate as signed with PKCS#1 v1.5, before sign_like_issuer signs again.
+fn with_pkcs1_algorithm(certificate: &[u8]) -> Result<Vec<u8>> {
+    let mut parts = der_sequence(certificate)?;
+    if parts.len() != 3 {
+        return Err(anyhow!("Invalid certificate"));
+    }
+    parts[1] = yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer
+                .next()
+                .write_oid(&ObjectIdentifier::from_slice(OID_SHA256_WITH_RSA));
+            writer.next().write_null();
+        })
+    });
+    Ok(to_der_sequence(&parts))
+}
+
+fn signed_with_pss(certificate: &[u8]) -> Result<bool> {
+    let (_, cert) = x509_parser::parse_x509_certificate(certificate)?;
+    Ok(cert.signature_algorithm.algorithm == OID_PKCS1_RSASSAPSS)
+}
+
+// The RSASSA-PSS-params of RFC 4055 matching sign_pss.
+fn pss_algorithm() -> Vec<u8> {
+    let sha256 = || ObjectIdentifier::from_slice(OID_SHA256);
+    yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer
+                .next()
+                .write_oid(&ObjectIdentifier::from_slice(OID_RSASSA_PSS));
+            writer.next().write_sequence(|writer| {
+                writer.next().write_tagged(Tag::context(0), |writer| {
+                    writer.write_sequence(|writer| writer.next().write_oid(&sha256()))
+                });
+                writer.next().write_tagged(Tag::context(1), |writer| {
+                    writer.write_sequence(|writer| {
+                        writer
+                            .next()
+                            .write_oid(&ObjectIdentifier::from_slice(OID_MGF1));
+                        writer
+                            .next()
+                            .write_sequence(|writer| writer.next().write_oid(&sha256()));
+                    })
+                });
+                writer
+                    .next()
+                    .write_tagged(Tag::context(2), |writer| writer.write_u8(32));
+            })
+        })
+    })
+}
+
+// Certificates issued by a CA whose certificate is signed with RSA-PSS are signed with RSA-PSS as well.
+fn sign_like_issuer(
+    certificate: Vec<u8>,
+    issuer: &Certificate,
+    issuer_pem: &[u8],
+) -> Result<Vec<u8>> {
+    if signed_with_pss(&first_certificate(issuer_pem)?)?
+        && issuer.get_key_pair().is_compatible(&PKCS_RSA_SHA256)
+    {
+        sign_pss(&certificate, &issuer.serialize_private_key_der())
+    } else {
+        Ok(certificate)
+    }
+}
+
+fn first_certificate(pem: &[u8]) -> Result<Vec<u8>> {
+    pem_certificates(pem)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No certificate found"))
+}
+
+fn chain_to_pem(chain: &[Vec<u8>]) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    for cert in chain {
+        content.extend(to_pem("CERTIFICATE", cert)?);
+    }
+    Ok(content)
+}
+
+fn write_to_file(file_name: &str, content: &[u8], resource_type: &str) -> Result<()> {
+    File::create(file_name)
+        .and_then(|mut file| file.write_all(content))
+        .map_err(|e| anyhow!("Cannot write the {} to {}: {}", resource_type, file_name, e))?;
+    log::debug!(
+        "{} was successfully written to file {}.",
+        resource_type,
+        file_name
+    );
+    Ok(())
 }
 
 fn read_from_file(file_name: &str) -> Vec<u8> {
@@ -170,6 +1162,15 @@ fn read_from_file(file_name: &str) -> Vec<u8> {
         .unwrap()
 }
 
+fn validity_days(days: Option<&str>) -> Result<i64> {
+    match days {
+        Some(d) => d.parse::<u32>().map(i64::from).map_err(|_| {
+            DrogueError::InvalidInput(format!("Invalid number of days: {}", d)).into()
+        }),
+        None => Ok(CERT_VALIDITY_DAYS),
+    }
+}
+
 fn generate_certificate(
     cert_type: CertificateType,
     common_name: &str,
@@ -177,26 +1178,20 @@ fn generate_certificate(
     key_pair_algorithm: Option<SignAlgo>,
     days: Option<&str>,
     key_input: Option<KeyPair>,
+    profile: &CertificateProfile,
 ) -> Result<Certificate> {
-    let mut params = CertificateParams::new(vec!["Drogue Iot".to_owned()]);
+    let mut params = CertificateParams::new(vec![]);
+    if !profile.has_subject_alt_names() {
+        params.subject_alt_names = vec![SanType::DnsName("Drogue Iot".to_owned())];
+    }
 
-    let valid_for: i64 = match days {
-        Some(d) => d.parse().unwrap(),
-        _ => CERT_VALIDITY_DAYS,
-    };
+    let valid_for = validity_days(days)?;
 
     params.not_before = Utc::now();
     params.not_after = Utc::now() + Duration::days(valid_for);
-    params
-        .distinguished_name
-        .push(DnType::OrganizationName, "Drogue IoT".to_owned());
-    params.distinguished_name.push(
-        DnType::OrganizationalUnitName,
-        organizational_unit.to_owned(),
-    );
-    params
-        .distinguished_name
-        .push(DnType::CommonName, common_name.to_owned());
+    // rcgen derives the default serial from the public key, which a renewal keeps
+    params.serial_number = Some(rand::random());
+    params.distinguished_name = subject_dn(common_name, organizational_unit, profile);
 
     params.key_pair = key_input;
 
@@ -204,88 +1199,227 @@ fn generate_certificate(
         Some(algo_name) => match algo_name {
             SignAlgo::ECDSA => &PKCS_ECDSA_P256_SHA256,
             SignAlgo::EdDSA => &PKCS_ED25519,
-            SignAlgo::EdDSA384 => &PKCS_ECDSA_P384_SHA384,
-            SignAlgo::RSA => {
-                params.key_pair = params.key_pair.or(Some(generate_rsa_key()?));
+            SignAlgo::ECDSA384 => &PKCS_ECDSA_P384_SHA384,
+            SignAlgo::RSA | SignAlgo::RSA3072 | SignAlgo::RSA4096 | SignAlgo::RSAPSS => {
+                // RSA-PSS signatures are made once rcgen signed the certificate, see sign_pss
+                if params.key_pair.is_none() {
+                    // Safe unwrap, all the RSA variants have a key size
+                    params.key_pair = Some(generate_rsa_key(algo_name.rsa_bits().unwrap())?);
+                }
                 &PKCS_RSA_SHA256
             }
         },
         _ => &PKCS_ECDSA_P256_SHA256, // Default Signature algorithm
     };
 
-    match cert_type {
-        CertificateType::app => {
-            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
-        }
-        CertificateType::device => {
-            params
-                .extended_key_usages
-                .push(ExtendedKeyUsagePurpose::ServerAuth);
-            params
-                .extended_key_usages
-                .push(ExtendedKeyUsagePurpose::ClientAuth);
-            params.key_identifier_method = KeyIdMethod::Sha256;
-        }
-    };
+    apply_profile_extensions(&mut params, cert_type, profile);
 
     Certificate::from_params(params)
         .map_err(|e| anyhow!("Error Generating certificate for {} : {}", common_name, e))
 }
 
+/// The CA signing an intermediate application certificate, instead of self-signing it.
+pub struct Issuer<'a> {
+    /// PEM file with the issuer certificate, optionally followed by the rest of its chain.
+    pub certificate: &'a str,
+    pub key: &'a str,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn create_trust_anchor(
     app_id: &str,
     keyout: Option<&str>,
     key_pair_algorithm: Option<SignAlgo>,
     days: Option<&str>,
     key_input: Option<KeyPair>,
+    profile: &CertificateProfile,
+    export: &ExportOptions,
+    issuer: Option<&Issuer>,
 ) -> Result<ApplicationSpecTrustAnchorEntry> {
-    const OU: &str = "Cloud";
     let is_input_key = key_input.is_some();
 
+    if export.format != ExportFormat::pem && keyout.is_none() {
+        return Err(anyhow!(
+            "{} output must be written to a file, use --key-output",
+            export.format.as_ref().to_uppercase()
+        ));
+    }
+
     let app_certificate = generate_certificate(
         CertificateType::app,
         app_id,
-        OU,
+        APP_OU,
         key_pair_algorithm,
         days,
         key_input,
+        profile,
     )?;
 
-    let pem_cert = app_certificate.serialize_pem()?;
-    log::debug!("Self-signed certificate generated.");
+    let chain = match issuer {
+        Some(issuer) => {
+            let issuer_pem = read_from_file(issuer.certificate);
+            let issuer_ca = load_ca_certificate(issuer.key, &issuer_pem)
+                .map_err(|e| anyhow!("Invalid issuer: {}", e))?;
+            let issuer_chain = pem_certificates(&issuer_pem)?;
 
-    let private_key = app_certificate.serialize_private_key_pem();
-    log::debug!("Private key extracted.");
+            let der_cert = sign_like_issuer(
+                app_certificate.serialize_der_with_signer(&issuer_ca)?,
+                &issuer_ca,
+                &issuer_pem,
+            )?;
+            verify_chain(&der_cert, &issuer_chain)?;
+            log::debug!("Intermediate certificate signed by {}.", issuer.certificate);
 
-    // Private key printed to terminal, when keyout argument not specified.
-    if !is_input_key {
-        match keyout {
-            Some(file_name) => write_to_file(file_name, &private_key, "App private key"),
-            _ => {
-                println!("Private key for an application is used to sign device certificates, see `drg trust add --help`\n");
-                println!("{}", &private_key)
+            [vec![der_cert], issuer_chain].concat()
+        }
+        None => {
+            log::debug!("Self-signed certificate generated.");
+            let der_cert = app_certificate.serialize_der()?;
+            if key_pair_algorithm == Some(SignAlgo::RSAPSS) {
+                vec![sign_pss(
+                    &der_cert,
+                    &app_certificate.serialize_private_key_der(),
+                )?]
+            } else {
+                vec![der_cert]
             }
         }
     };
 
+    let private_key = app_certificate.serialize_private_key_der();
+    log::debug!("Private key extracted.");
+
+    if export.format == ExportFormat::p12 {
+        let chain: Vec<&[u8]> = chain.iter().map(Vec::as_slice).collect();
+        let bundle = export.pkcs12(app_id, &private_key, &chain)?;
+        // Safe unwrap, the output file was checked above
+        write_to_file(keyout.unwrap(), &bundle, "App PKCS#12 bundle")?;
+    } else if !is_input_key {
+        write_app_key(keyout, &private_key, export)?;
+    };
+
     Ok(ApplicationSpecTrustAnchorEntry {
-        certificate: pem_cert.into_bytes(),
+        certificate: chain_to_pem(&chain)?,
     })
 }
 
-fn generate_rsa_key() -> Result<KeyPair> {
-    const RSA_BIT_SIZE: usize = 2048;
+/// Generate the application CA key and a certificate signing request, to be signed offline.
+///
+/// The basic constraints and key usages are not part of the request, the signing CA sets them.
+pub fn create_trust_anchor_request(
+    app_id: &str,
+    keyout: Option<&str>,
+    key_pair_algorithm: Option<SignAlgo>,
+    key_input: Option<KeyPair>,
+    profile: &CertificateProfile,
+    export: &ExportOptions,
+    csr_out: &str,
+) -> Result<Outcome<String>> {
+    let is_input_key = key_input.is_some();
+
+    match export.format {
+        ExportFormat::p12 => {
+            return Err(anyhow!(
+                "A PKCS#12 bundle needs the signed certificate, export the key as PEM or DER"
+            ))
+        }
+        ExportFormat::der if keyout.is_none() && !is_input_key => {
+            return Err(anyhow!(
+                "DER output must be written to a file, use --key-output"
+            ))
+        }
+        _ => {}
+    }
+
+    let app_certificate = generate_certificate(
+        CertificateType::app,
+        app_id,
+        APP_OU,
+        key_pair_algorithm,
+        None,
+        key_input,
+        profile,
+    )?;
+
+    let request = app_certificate.serialize_request_der()?;
+    write_to_file(
+        csr_out,
+        &export.certificate_request(&request)?,
+        "App certificate signing request",
+    )?;
+
+    if !is_input_key {
+        write_app_key(keyout, &app_certificate.serialize_private_key_der(), export)?;
+    }
+
+    Ok(Outcome::SuccessWithMessage(format!(
+        "Certificate signing request written to {}. Once signed, upload it with --import-signed",
+        csr_out
+    )))
+}
+
+/// Read an application CA certificate signed offline, checking it belongs to the given key.
+///
+/// The issuer chain is taken from the signed file, or from `issuer_cert` when it only contains
+/// the application certificate.
+pub fn import_trust_anchor(
+    signed: &str,
+    key: &KeyPair,
+    issuer_cert: Option<&str>,
+) -> Result<ApplicationSpecTrustAnchorEntry> {
+    let mut chain = pem_certificates(&read_from_file(signed))
+        .map_err(|e| anyhow!("Cannot read signed certificate {}: {}", signed, e))?;
+    if let Some(issuer_cert) = issuer_cert {
+        chain.extend(pem_certificates(&read_from_file(issuer_cert))?);
+    }
 
+    let app_cert = chain.remove(0);
+    {
+        let cert = x509_parser::parse_x509_certificate(&app_cert)?.1;
+        if cert.tbs_certificate.subject_pki.subject_public_key.data != key.public_key_raw() {
+            return Err(anyhow!(
+                "The signed certificate does not match the application key"
+            ));
+        }
+        if !cert.tbs_certificate.is_ca() {
+            return Err(anyhow!(
+                "The signed certificate is not a CA certificate and cannot sign device certificates"
+            ));
+        }
+    }
+    verify_chain(&app_cert, &chain)?;
+    chain.insert(0, app_cert);
+
+    Ok(ApplicationSpecTrustAnchorEntry {
+        certificate: chain_to_pem(&chain)?,
+    })
+}
+
+fn write_app_key(keyout: Option<&str>, private_key: &[u8], export: &ExportOptions) -> Result<()> {
+    // Private key printed to terminal, when keyout argument not specified.
+    let private_key = export.private_key(private_key)?;
+    match keyout {
+        Some(file_name) => write_to_file(file_name, &private_key, "App private key")?,
+        _ => {
+            println!("Private key for an application is used to sign device certificates, see `drg trust add --help`\n");
+            println!("{}", from_utf8(&private_key)?)
+        }
+    }
+    Ok(())
+}
+
+fn generate_rsa_key(bits: usize) -> Result<KeyPair> {
     let mut rng = OsRng;
-    let private_key = RsaPrivateKey::new(&mut rng, RSA_BIT_SIZE)?;
+    let private_key = RsaPrivateKey::new(&mut rng, bits)?;
     let pkcs8_key = &private_key.to_pkcs8_der()?;
 
-    KeyPair::from_der(pkcs8_key.as_ref()).map_err(|e| anyhow!("RSA key generation failed: {}", e))
+    KeyPair::from_der(pkcs8_key.as_bytes()).map_err(|e| anyhow!("RSA key generation failed: {}", e))
 }
 
 #[cfg(test)]
 mod trust_test {
     use super::*;
+    use crate::util::IssuedStatus;
     use std::path::Path;
 
     const CERT: &str = r#"-----BEGIN CERTIFICATE-----
@@ -303,12 +1437,21 @@ PYI8dV0jurxjGr8CIHrq3O4gtrixHDs6GDyzKf4CMOg37phempNDnp0/twP7
 
     #[test]
     fn test_create_trust_anchor() {
-        let resp = create_trust_anchor("app10", Some("key.pem"), None, None, None).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let key_file = dir.path().join("key.pem");
+        let resp = create_trust_anchor(
+            "app10",
+            key_file.to_str(),
+            None,
+            None,
+            None,
+            &CertificateProfile::default(),
+            &ExportOptions::default(),
+            None,
+        )
+        .unwrap();
         assert!(!resp.certificate.is_empty(), "Invalid JSON response.");
-        assert!(
-            Path::new("key.pem").is_file(),
-            "Error exporting private key to file."
-        );
+        assert!(key_file.is_file(), "Error exporting private key to file.");
 
         let resp_cert_pem = from_utf8(&resp.certificate).unwrap();
 
@@ -320,35 +1463,36 @@ PYI8dV0jurxjGr8CIHrq3O4gtrixHDs6GDyzKf4CMOg37phempNDnp0/twP7
 
     #[test]
     fn test_create_device_certificate() {
+        let (_index_dir, index) = temp_index();
+        let dir = tempfile::tempdir().unwrap();
+        let key_file = dir.path().join("device-key.pem");
+        let cert_file = dir.path().join("device-cert.pem");
         assert!(
             create_device_certificate(
                 "app10",
                 "d5",
                 "keys/test-app-key.pem",
                 CERT.as_bytes(),
-                Some("device-key.pem"),
-                Some("device-cert.pem"),
+                key_file.to_str(),
+                cert_file.to_str(),
+                None,
                 None,
                 None,
-                None
+                &CertificateProfile::default(),
+                &ExportOptions::default(),
+                &index,
             )
             .is_ok(),
             "Unable to generate device certificate."
         );
 
-        assert!(
-            Path::new("device-key.pem").is_file(),
-            "Error exporting private key to file."
-        );
-
-        assert!(
-            Path::new("device-cert.pem").is_file(),
-            "Error exporting certificate to file."
-        );
+        assert!(key_file.is_file(), "Error exporting private key to file.");
+        assert!(cert_file.is_file(), "Error exporting certificate to file.");
     }
 
     #[test]
     fn test_key_certificate_mismatch() {
+        let (_index_dir, index) = temp_index();
         assert!(
             create_device_certificate(
                 "app10",
@@ -359,7 +1503,10 @@ PYI8dV0jurxjGr8CIHrq3O4gtrixHDs6GDyzKf4CMOg37phempNDnp0/twP7
                 None,
                 None,
                 None,
-                None
+                None,
+                &CertificateProfile::default(),
+                &ExportOptions::default(),
+                &index,
             )
             .is_err(),
             "CA key and certificate mismatch should terminate with an error."
@@ -375,18 +1522,592 @@ PYI8dV0jurxjGr8CIHrq3O4gtrixHDs6GDyzKf4CMOg37phempNDnp0/twP7
                 None,
                 Some(key_input.1),
                 Some("256"),
-                Some(key_input.0)
+                Some(key_input.0),
+                &CertificateProfile::default(),
+                &ExportOptions::default(),
+                None,
             )
             .is_ok(),
             "Adding custom RSA key failed."
         );
     }
 
+    fn temp_index() -> (tempfile::TempDir, CertIndex) {
+        let dir = tempfile::tempdir().unwrap();
+        let index = CertIndex::new(dir.path().to_path_buf());
+        (dir, index)
+    }
+
+    fn write_device_csr(common_name: &str, app_id: &str) -> tempfile::NamedTempFile {
+        let mut params = CertificateParams::new(vec![]);
+        params.distinguished_name = subject_dn(common_name, app_id, &CertificateProfile::default());
+        let csr = Certificate::from_params(params)
+            .unwrap()
+            .serialize_request_pem()
+            .unwrap();
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(csr.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_sign_device_csr() {
+        let (_index_dir, index) = temp_index();
+        let csr = write_device_csr("d6", "app10");
+
+        assert!(
+            sign_device_csr(
+                "app10",
+                "d6",
+                "keys/test-app-key.pem",
+                CERT.as_bytes(),
+                csr.path().to_str().unwrap(),
+                None,
+                None,
+                &CertificateProfile::default(),
+                &KeyPolicy::default(),
+                &ExportOptions::default(),
+                &index,
+            )
+            .is_ok(),
+            "Unable to sign device CSR."
+        );
+    }
+
+    #[test]
+    fn test_invalid_validity_days() {
+        let (_index_dir, index) = temp_index();
+        let csr = write_device_csr("d6", "app10");
+
+        let err = sign_device_csr(
+            "app10",
+            "d6",
+            "keys/test-app-key.pem",
+            CERT.as_bytes(),
+            csr.path().to_str().unwrap(),
+            None,
+            Some("99999999999"),
+            &CertificateProfile::default(),
+            &KeyPolicy::default(),
+            &ExportOptions::default(),
+            &index,
+        )
+        .err()
+        .expect("The number of days should be rejected");
+        assert!(matches!(
+            err.downcast_ref::<DrogueError>(),
+            Some(DrogueError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_csr_key_policy() {
+        let (_index_dir, index) = temp_index();
+        let csr = write_device_csr("d6", "app10");
+        let sign = |policy: &KeyPolicy| {
+            sign_device_csr(
+                "app10",
+                "d6",
+                "keys/test-app-key.pem",
+                CERT.as_bytes(),
+                csr.path().to_str().unwrap(),
+                None,
+                None,
+                &CertificateProfile::default(),
+                policy,
+                &ExportOptions::default(),
+                &index,
+            )
+        };
+
+        // The CSR key is a P-256 one
+        let rsa_only = KeyPolicy {
+            allowed_algorithms: vec![SignAlgo::RSA],
+            min_rsa_bits: None,
+        };
+        assert!(sign(&rsa_only).is_err());
+        assert!(index.list().unwrap().is_empty());
+
+        let ecdsa = KeyPolicy {
+            allowed_algorithms: vec![SignAlgo::ECDSA],
+            min_rsa_bits: None,
+        };
+        assert!(sign(&ecdsa).is_ok());
+    }
+
+    #[test]
+    fn test_csr_subject_mismatch() {
+        let (_index_dir, index) = temp_index();
+        let csr = write_device_csr("another-device", "app10");
+
+        assert!(
+            sign_device_csr(
+                "app10",
+                "d6",
+                "keys/test-app-key.pem",
+                CERT.as_bytes(),
+                csr.path().to_str().unwrap(),
+                None,
+                None,
+                &CertificateProfile::default(),
+                &KeyPolicy::default(),
+                &ExportOptions::default(),
+                &index,
+            )
+            .is_err(),
+            "A CSR with an unexpected subject should be rejected."
+        );
+    }
+
     #[test]
     fn test_rsa_key_gen() {
         assert!(
-            create_trust_anchor("app40", None, Some(SignAlgo::RSA), None, None).is_ok(),
+            create_trust_anchor(
+                "app40",
+                None,
+                Some(SignAlgo::RSA),
+                None,
+                None,
+                &CertificateProfile::default(),
+                &ExportOptions::default(),
+                None,
+            )
+            .is_ok(),
             "RSA Key generation failed."
         );
     }
+
+    #[test]
+    fn test_certificate_profile() {
+        let profile = CertificateProfile {
+            organization: Some("ACME".to_string()),
+            country: Some("FR".to_string()),
+            dns_names: vec!["device.example.com".to_string()],
+            uris: vec!["urn:acme:device:d7".to_string()],
+            ip_addresses: vec!["192.168.0.7".parse().unwrap()],
+            ..Default::default()
+        };
+
+        let cert = generate_certificate(
+            CertificateType::device,
+            "d7",
+            "app10",
+            None,
+            None,
+            None,
+            &profile,
+        )
+        .unwrap()
+        .serialize_der()
+        .unwrap();
+        let (_, cert) = x509_parser::parse_x509_certificate(&cert).unwrap();
+
+        assert_eq!(cert.subject().to_string(), "O=ACME, OU=app10, C=FR, CN=d7");
+        assert_eq!(
+            device_alias("d7", "app10", &CertificateProfile::default()),
+            "CN=d7, O=Drogue IoT, OU=app10"
+        );
+        assert_eq!(
+            device_alias("d7", "app10", &profile),
+            "CN=d7, O=ACME, OU=app10, C=FR"
+        );
+        assert_eq!(
+            device_alias("d7, rev 2", "app10", &CertificateProfile::default()),
+            "CN=d7, rev 2, O=Drogue IoT, OU=app10"
+        );
+
+        let (_, sans) = cert.tbs_certificate.subject_alternative_name().unwrap();
+        let sans: Vec<String> = sans
+            .general_names
+            .iter()
+            .map(|name| format!("{:?}", name))
+            .collect();
+        assert_eq!(
+            sans,
+            vec![
+                "DNSName(\"device.example.com\")",
+                "URI(\"urn:acme:device:d7\")",
+                "IPAddress([192, 168, 0, 7])",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_export_encrypted_key() {
+        let (_index_dir, index) = temp_index();
+        let dir = tempfile::tempdir().unwrap();
+        let key_file = dir.path().join("device-key.pem");
+        let export = ExportOptions {
+            format: ExportFormat::pem,
+            passphrase: Some("secret".to_string()),
+        };
+
+        create_device_certificate(
+            "app10",
+            "d8",
+            "keys/test-app-key.pem",
+            CERT.as_bytes(),
+            key_file.to_str(),
+            dir.path().join("device-cert.pem").to_str(),
+            None,
+            None,
+            None,
+            &CertificateProfile::default(),
+            &export,
+            &index,
+        )
+        .unwrap();
+
+        let pem = fs::read_to_string(key_file).unwrap();
+        let (label, der) = pkcs8::der::Document::from_pem(&pem).unwrap();
+        assert_eq!(label, "ENCRYPTED PRIVATE KEY");
+
+        let encrypted = pkcs8::EncryptedPrivateKeyInfo::try_from(der.as_bytes()).unwrap();
+        assert!(encrypted.decrypt("wrong").is_err());
+        let key = encrypted.decrypt("secret").unwrap();
+        assert!(KeyPair::from_der(key.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_export_pkcs12() {
+        let (_index_dir, index) = temp_index();
+        let dir = tempfile::tempdir().unwrap();
+        let bundle_file = dir.path().join("device.p12");
+        let export = ExportOptions {
+            format: ExportFormat::p12,
+            passphrase: Some("secret".to_string()),
+        };
+
+        create_device_certificate(
+            "app10",
+            "d9",
+            "keys/test-app-key.pem",
+            CERT.as_bytes(),
+            None,
+            bundle_file.to_str(),
+            None,
+            None,
+            None,
+            &CertificateProfile::default(),
+            &export,
+            &index,
+        )
+        .unwrap();
+
+        let keystore = KeyStore::from_pkcs12(&fs::read(bundle_file).unwrap(), "secret").unwrap();
+        let (_, chain) = keystore.private_key_chain().unwrap();
+        assert!(KeyPair::from_der(chain.key()).is_ok());
+        assert_eq!(chain.chain().len(), 2);
+        assert_eq!(
+            chain.chain()[1].subject(),
+            "CN=trust, O=Drogue IoT, OU=Cloud"
+        );
+    }
+
+    #[test]
+    fn test_load_key_formats() {
+        let rsa = verify_input_key("keys/test-rsa-pkcs1.pem").unwrap();
+        assert!(matches!(rsa.1, SignAlgo::RSA));
+
+        let p256 = verify_input_key("keys/test-ec-sec1.pem").unwrap();
+        assert!(matches!(p256.1, SignAlgo::ECDSA));
+
+        let p384 = verify_input_key("keys/test-p384-sec1.der").unwrap();
+        assert!(matches!(p384.1, SignAlgo::ECDSA384));
+
+        std::env::set_var(KEY_PASSPHRASE_ENV, "drogue");
+        let encrypted = verify_input_key("keys/test-encrypted-pk8.pem").unwrap();
+        assert!(matches!(encrypted.1, SignAlgo::ECDSA));
+    }
+
+    #[test]
+    fn test_renew_device_certificate() {
+        let (_index_dir, index) = temp_index();
+        let profile = CertificateProfile::default();
+        let export = ExportOptions::default();
+
+        create_device_certificate(
+            "app10",
+            "d10",
+            "keys/test-app-key.pem",
+            CERT.as_bytes(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            &profile,
+            &export,
+            &index,
+        )
+        .unwrap();
+
+        renew_device_certificate(
+            "app10",
+            "d10",
+            "keys/test-app-key.pem",
+            CERT.as_bytes(),
+            None,
+            Some("30"),
+            &profile,
+            &KeyPolicy::default(),
+            &export,
+            &index,
+        )
+        .unwrap();
+
+        let issued = index.list().unwrap();
+        assert_eq!(issued.len(), 2);
+        assert_eq!(issued[0].status, IssuedStatus::Superseded);
+        assert_eq!(issued[1].status, IssuedStatus::Valid);
+        assert_ne!(issued[0].serial, issued[1].serial);
+        assert_eq!(issued[0].key_fingerprint, issued[1].key_fingerprint);
+        assert_eq!(index.latest("d10").unwrap().serial, issued[1].serial);
+
+        // The index is left untouched when the certificate cannot be written
+        let renewed = renew_device_certificate(
+            "app10",
+            "d10",
+            "keys/test-app-key.pem",
+            CERT.as_bytes(),
+            Some("/nonexistent/d10.pem"),
+            None,
+            &profile,
+            &KeyPolicy::default(),
+            &export,
+            &index,
+        );
+        assert!(renewed.is_err());
+        assert_eq!(index.list().unwrap().len(), 2);
+        assert_eq!(index.latest("d10").unwrap().serial, issued[1].serial);
+    }
+
+    // A root CA, written to files so it can be used as an issuer.
+    fn root_ca(dir: &Path) -> (String, String) {
+        let cert = dir.join("root.pem");
+        let key = dir.join("root-key.pem");
+        let root = create_trust_anchor(
+            "root",
+            key.to_str(),
+            None,
+            None,
+            None,
+            &CertificateProfile::default(),
+            &ExportOptions::default(),
+            None,
+        )
+        .unwrap();
+        fs::write(&cert, root.certificate).unwrap();
+
+        (
+            cert.to_str().unwrap().to_string(),
+            key.to_str().unwrap().to_string(),
+        )
+    }
+
+    #[test]
+    fn test_intermediate_trust_anchor() {
+        let (_index_dir, index) = temp_index();
+        let dir = tempfile::tempdir().unwrap();
+        let (root_cert, root_key) = root_ca(dir.path());
+        let app_key = dir.path().join("app-key.pem");
+
+        let anchor = create_trust_anchor(
+            "app50",
+            app_key.to_str(),
+            None,
+            None,
+            None,
+            &CertificateProfile::default(),
+            &ExportOptions::default(),
+            Some(&Issuer {
+                certificate: &root_cert,
+                key: &root_key,
+            }),
+        )
+        .unwrap();
+
+        let chain = pem_certificates(&anchor.certificate).unwrap();
+        assert_eq!(chain.len(), 2);
+        let intermediate = x509_parser::parse_x509_certificate(&chain[0]).unwrap().1;
+        assert!(intermediate.tbs_certificate.is_ca());
+        assert_eq!(
+            intermediate.issuer().to_string(),
+            "O=Drogue IoT, OU=Cloud, CN=root"
+        );
+
+        create_device_certificate(
+            "app50",
+            "d11",
+            app_key.to_str().unwrap(),
+            &anchor.certificate,
+            None,
+            None,
+            None,
+            None,
+            None,
+            &CertificateProfile::default(),
+            &ExportOptions::default(),
+            &index,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_import_signed_trust_anchor() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root_cert, root_key) = root_ca(dir.path());
+        let app_key = dir.path().join("app-key.pem");
+        let csr = dir.path().join("app.csr");
+
+        create_trust_anchor_request(
+            "app51",
+            app_key.to_str(),
+            None,
+            None,
+            &CertificateProfile::default(),
+            &ExportOptions::default(),
+            csr.to_str().unwrap(),
+        )
+        .unwrap();
+
+        // Offline signing by the root CA
+        let root = load_ca_certificate(&root_key, &fs::read(&root_cert).unwrap()).unwrap();
+        let mut request =
+            CertificateSigningRequest::from_pem(&fs::read_to_string(&csr).unwrap()).unwrap();
+        request.params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+        let signed = dir.path().join("app-signed.pem");
+        fs::write(&signed, request.serialize_pem_with_signer(&root).unwrap()).unwrap();
+
+        let key = load_key_pair(app_key.to_str().unwrap()).unwrap();
+        let anchor = import_trust_anchor(signed.to_str().unwrap(), &key, Some(&root_cert)).unwrap();
+        assert_eq!(pem_certificates(&anchor.certificate).unwrap().len(), 2);
+
+        // The signed certificate must belong to the application key
+        let other_key = load_key_pair("keys/test-app-key.pem").unwrap();
+        assert!(
+            import_trust_anchor(signed.to_str().unwrap(), &other_key, Some(&root_cert)).is_err()
+        );
+    }
+
+    #[test]
+    fn test_chain_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root_cert, _) = root_ca(dir.path());
+        let device_cert = generate_certificate(
+            CertificateType::device,
+            "d12",
+            "app10",
+            None,
+            None,
+            None,
+            &CertificateProfile::default(),
+        )
+        .unwrap()
+        .serialize_der()
+        .unwrap();
+
+        let chain = pem_certificates(&fs::read(root_cert).unwrap()).unwrap();
+        assert!(verify_chain(&device_cert, &chain).is_err());
+    }
+
+    #[test]
+    fn test_p384_trust_anchor() {
+        let anchor = create_trust_anchor(
+            "app52",
+            None,
+            Some(SignAlgo::ECDSA384),
+            None,
+            None,
+            &CertificateProfile::default(),
+            &ExportOptions::default(),
+            None,
+        )
+        .unwrap();
+
+        let der = pem_certificates(&anchor.certificate).unwrap().remove(0);
+        let cert = x509_parser::parse_x509_certificate(&der).unwrap().1;
+        assert_eq!(
+            cert.signature_algorithm.algorithm,
+            x509_parser::oid_registry::OID_SIG_ECDSA_WITH_SHA384
+        );
+    }
+
+    #[test]
+    fn test_rsa_pss_certificates() {
+        let (_index_dir, index) = temp_index();
+        let dir = tempfile::tempdir().unwrap();
+        let app_key = dir.path().join("app-key.pem");
+        let device_cert = dir.path().join("device-cert.pem");
+
+        let anchor = create_trust_anchor(
+            "app53",
+            app_key.to_str(),
+            Some(SignAlgo::RSAPSS),
+            None,
+            None,
+            &CertificateProfile::default(),
+            &ExportOptions::default(),
+            None,
+        )
+        .unwrap();
+        let chain = pem_certificates(&anchor.certificate).unwrap();
+        let cert = x509_parser::parse_x509_certificate(&chain[0]).unwrap().1;
+        assert_eq!(cert.signature_algorithm.algorithm, OID_PKCS1_RSASSAPSS);
+        assert_eq!(
+            cert.tbs_certificate.signature.algorithm,
+            OID_PKCS1_RSASSAPSS
+        );
+        verify_chain(&chain[0], &[]).unwrap();
+
+        // The certificates issued by an RSA-PSS trust anchor are signed the same way
+        create_device_certificate(
+            "app53",
+            "d53",
+            app_key.to_str().unwrap(),
+            &anchor.certificate,
+            Some(dir.path().join("device-key.pem").to_str().unwrap()),
+            device_cert.to_str(),
+            None,
+            None,
+            None,
+            &CertificateProfile::default(),
+            &ExportOptions::default(),
+            &index,
+        )
+        .unwrap();
+        let der = pem_certificates(&fs::read(&device_cert).unwrap())
+            .unwrap()
+            .remove(0);
+        let cert = x509_parser::parse_x509_certificate(&der).unwrap().1;
+        assert_eq!(cert.signature_algorithm.algorithm, OID_PKCS1_RSASSAPSS);
+        verify_chain(&der, &chain).unwrap();
+    }
+
+    #[test]
+    fn test_rsa_key_size() {
+        let key = generate_rsa_key(3072).unwrap();
+        assert_eq!(rsa_key_bits(&key).unwrap(), 3072);
+        assert_eq!(SignAlgo::rsa(3072).unwrap(), SignAlgo::RSA3072);
+        assert!(SignAlgo::rsa(1024).is_err());
+    }
+
+    #[test]
+    fn test_key_policy() {
+        let policy = KeyPolicy {
+            allowed_algorithms: vec![SignAlgo::ECDSA384, SignAlgo::RSA],
+            min_rsa_bits: Some(3072),
+        };
+
+        assert!(policy.check(SignAlgo::ECDSA384, None).is_ok());
+        assert!(policy.check(SignAlgo::RSA4096, None).is_ok());
+        assert!(policy.check(SignAlgo::ECDSA, None).is_err());
+        assert!(policy.check(SignAlgo::RSA, None).is_err());
+        // Imported keys are checked for their actual size
+        assert!(policy.check(SignAlgo::RSA, Some(1024)).is_err());
+        assert!(KeyPolicy::default()
+            .check(SignAlgo::RSA, Some(1024))
+            .is_ok());
+    }
 }
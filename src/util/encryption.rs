@@ -0,0 +1,138 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine as _};
+use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, KeyInit};
+use core::fmt;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+/// The passphrase protecting the tokens saved in the configuration file.
+pub const CONFIG_PASSPHRASE_ENV: &str = "DRG_CONFIG_PASSPHRASE";
+
+const CIPHER: &str = "scrypt-chacha20poly1305";
+// scrypt parameters recommended for interactive use: 2^15 iterations, 32MiB of memory.
+const LOG_N: u8 = 15;
+
+/// Data encrypted with a passphrase.
+///
+/// The key is derived from the passphrase with scrypt, and the data is encrypted and
+/// authenticated with ChaCha20-Poly1305.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Encrypted {
+    pub cipher: String,
+    pub log_n: u8,
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+/// A passphrase, kept out of the debug output.
+#[derive(Clone)]
+pub struct Passphrase(String);
+
+impl Passphrase {
+    /// Read the passphrase from `DRG_CONFIG_PASSPHRASE`, or prompt for it.
+    pub fn read(prompt: &str, confirm: bool) -> Result<Self> {
+        read_passphrase(CONFIG_PASSPHRASE_ENV, prompt, confirm).map(Passphrase)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn new(passphrase: &str) -> Self {
+        Passphrase(passphrase.to_string())
+    }
+}
+
+/// Read a passphrase from an environment variable, or prompt for it without echoing it.
+/// A new passphrase is asked twice, to catch typos.
+pub fn read_passphrase(env: &str, prompt: &str, confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(env) {
+        return Ok(passphrase);
+    }
+
+    let passphrase = rpassword::prompt_password(prompt)?;
+    if passphrase.is_empty() {
+        return Err(anyhow!("The passphrase cannot be empty"));
+    }
+    if confirm && rpassword::prompt_password("Confirm the passphrase: ")? != passphrase {
+        return Err(anyhow!("The passphrases do not match"));
+    }
+    Ok(passphrase)
+}
+
+impl fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Passphrase(***)")
+    }
+}
+
+pub fn encrypt(passphrase: &Passphrase, plaintext: &[u8]) -> Result<Encrypted> {
+    encrypt_with_cost(passphrase, plaintext, LOG_N)
+}
+
+fn encrypt_with_cost(passphrase: &Passphrase, plaintext: &[u8], log_n: u8) -> Result<Encrypted> {
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let ciphertext = derive_cipher(passphrase, &salt, log_n)?
+        .encrypt(&nonce.into(), plaintext)
+        .map_err(|_| anyhow!("Cannot encrypt the data"))?;
+
+    Ok(Encrypted {
+        cipher: CIPHER.to_string(),
+        log_n,
+        salt: general_purpose::STANDARD.encode(salt),
+        nonce: general_purpose::STANDARD.encode(nonce),
+        ciphertext: general_purpose::STANDARD.encode(ciphertext),
+    })
+}
+
+pub fn decrypt(passphrase: &Passphrase, data: &Encrypted) -> Result<Vec<u8>> {
+    if data.cipher != CIPHER {
+        return Err(anyhow!("Unsupported encryption: {}", data.cipher));
+    }
+
+    let salt = general_purpose::STANDARD.decode(&data.salt)?;
+    let nonce = general_purpose::STANDARD.decode(&data.nonce)?;
+    let ciphertext = general_purpose::STANDARD.decode(&data.ciphertext)?;
+    if nonce.len() != 12 {
+        return Err(anyhow!("Invalid encrypted data"));
+    }
+
+    derive_cipher(passphrase, &salt, data.log_n)?
+        .decrypt(nonce.as_slice().into(), ciphertext.as_slice())
+        .map_err(|_| anyhow!("Cannot decrypt, wrong passphrase?"))
+}
+
+fn derive_cipher(passphrase: &Passphrase, salt: &[u8], log_n: u8) -> Result<ChaCha20Poly1305> {
+    let params =
+        scrypt::Params::new(log_n, 8, 1, 32).map_err(|e| anyhow!("Invalid scrypt cost: {}", e))?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(passphrase.0.as_bytes(), salt, &params, &mut key)
+        .map_err(|e| anyhow!("Cannot derive the key: {}", e))?;
+
+    Ok(ChaCha20Poly1305::new(&key.into()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encryption_round_trip() {
+        let passphrase = Passphrase("correct horse".to_string());
+        let encrypted = encrypt_with_cost(&passphrase, b"some token", 4).unwrap();
+
+        assert_eq!(encrypted.cipher, CIPHER);
+        assert_eq!(decrypt(&passphrase, &encrypted).unwrap(), b"some token");
+
+        let wrong = Passphrase("battery staple".to_string());
+        assert!(decrypt(&wrong, &encrypted).is_err());
+
+        let mut tampered = encrypted.clone();
+        tampered.ciphertext = encrypt_with_cost(&passphrase, b"other token", 4)
+            .unwrap()
+            .ciphertext;
+        assert!(decrypt(&passphrase, &tampered).is_err());
+    }
+}
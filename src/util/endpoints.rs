@@ -69,7 +69,7 @@ pub async fn get_drogue_websocket_endpoint(context: &Context) -> anyhow::Result<
 
 // use keycloak's well known endpoint to retrieve endpoints.
 // http://keycloakhost:keycloakport/auth/realms/{realm}/.well-known/openid-configuration
-pub async fn get_auth_and_tokens_endpoints(issuer_url: Url) -> anyhow::Result<(Url, Url)> {
+async fn openid_configuration(issuer_url: &Url) -> anyhow::Result<Value> {
     let client = reqwest::Client::new();
 
     let url = issuer_url.join(".well-known/openid-configuration")?;
@@ -79,10 +79,13 @@ pub async fn get_auth_and_tokens_endpoints(issuer_url: Url) -> anyhow::Result<(U
         .await
         .context("Can't retrieve openid-connect endpoints details")?;
 
-    let endpoints: Value = res
-        .json()
+    res.json()
         .await
-        .context("Cannot deserialize openid-connect endpoints details")?;
+        .context("Cannot deserialize openid-connect endpoints details")
+}
+
+pub async fn get_auth_and_tokens_endpoints(issuer_url: Url) -> anyhow::Result<(Url, Url)> {
+    let endpoints = openid_configuration(&issuer_url).await?;
 
     let (auth, token) = endpoints["authorization_endpoint"]
         .as_str()
@@ -93,6 +96,27 @@ pub async fn get_auth_and_tokens_endpoints(issuer_url: Url) -> anyhow::Result<(U
     Ok((auth?, token?))
 }
 
+/// The endpoint starting the OAuth device authorization grant (RFC 8628).
+pub async fn get_device_authorization_endpoint(issuer_url: Url) -> anyhow::Result<Url> {
+    let endpoints = openid_configuration(&issuer_url).await?;
+
+    let device = endpoints["device_authorization_endpoint"]
+        .as_str()
+        .context("The openid-connect provider does not support the device authorization grant")?;
+
+    url_validation(device)
+}
+
+/// The token revocation endpoint (RFC 7009), if the openid-connect provider has one.
+pub async fn get_revocation_endpoint(issuer_url: Url) -> anyhow::Result<Option<Url>> {
+    let endpoints = openid_configuration(&issuer_url).await?;
+
+    endpoints["revocation_endpoint"]
+        .as_str()
+        .map(url_validation)
+        .transpose()
+}
+
 // pub async fn print_endpoints(context: &Context) -> Result<Outcome<Endpoints>, DrogueError> {
 //     get_drogue_endpoints_authenticated(context).await
 //         .map(|e| Outcome::SuccessWithJsonData(e))
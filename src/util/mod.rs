@@ -1,12 +1,16 @@
+mod cert_index;
 mod certs;
 mod display;
+mod encryption;
 mod endpoints;
 mod error;
 mod operations;
 mod outcome;
 
+pub use cert_index::*;
 pub use certs::*;
 pub use display::*;
+pub use encryption::*;
 pub use endpoints::*;
 pub use error::*;
 pub use outcome::*;
@@ -76,3 +76,27 @@ fn create_device_certificate(app: String) {
 
     app_delete(app.clone());
 }
+
+#[rstest]
+fn audit_expiring_trust_anchor(app: String) {
+    retry_409!(
+        3,
+        drg!()
+            .arg("create")
+            .arg("app-cert")
+            .arg("--days")
+            .arg("10")
+            .arg("--application")
+            .arg(app.clone())
+    );
+
+    drg!()
+        .arg("audit")
+        .arg("certs")
+        .arg("--within")
+        .arg("30d")
+        .assert()
+        .code(2);
+
+    app_delete(app.clone());
+}